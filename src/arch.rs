@@ -0,0 +1,257 @@
+use crate::{Dependency, PackageBuild};
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+
+/// The architecture that allows a packagebuild to be built for every architecture
+pub const ARCH_ANY: &str = "any";
+
+/// The fields of a packagebuild overridden for a specific architecture,
+/// declared as `<key>_<arch>`, such as `source_aarch64` or `build_dependencies_x86_64`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ArchOverride {
+    /// The architecture the overrides apply to
+    pub arch: String,
+    /// Replaces `source`
+    pub source: Option<String>,
+    /// Replaces `source_sha256`
+    pub source_sha256: Option<String>,
+    /// Replaces `extra_dependencies`
+    pub extra_dependencies: Option<Vec<Dependency>>,
+    /// Replaces `optional_dependencies`
+    pub optional_dependencies: Option<Vec<Dependency>>,
+    /// Replaces `build_dependencies`
+    pub build_dependencies: Option<Vec<Dependency>>,
+    /// Replaces `cross_dependencies`
+    pub cross_dependencies: Option<Vec<Dependency>>,
+
+    /// Replaces the `prepare` phase
+    pub prepare: Option<Vec<String>>,
+    /// Replaces the `build` phase
+    pub build: Option<Vec<String>>,
+    /// Replaces the `check` phase
+    pub check: Option<Vec<String>>,
+    /// Replaces the `package` phase
+    pub package: Option<Vec<String>>,
+}
+
+impl ArchOverride {
+    /// Creates a new architecture override that does not override anything
+    /// # Arguments
+    /// * `arch` - The architecture the overrides apply to
+    pub fn new(arch: &str) -> Self {
+        Self {
+            arch: arch.to_owned(),
+            source: None,
+            source_sha256: None,
+            extra_dependencies: None,
+            optional_dependencies: None,
+            build_dependencies: None,
+            cross_dependencies: None,
+
+            prepare: None,
+            build: None,
+            check: None,
+            package: None,
+        }
+    }
+}
+
+impl PackageBuild {
+    /// Checks if this packagebuild can be built for the supplied architecture:
+    /// If it does not list any architectures, lists `any` or lists the architecture
+    /// # Arguments
+    /// * `arch` - The architecture to check
+    pub fn supports_arch(&self, arch: &str) -> bool {
+        match &self.arch {
+            None => true,
+            Some(archs) => archs.iter().any(|a| a == ARCH_ANY || a == arch),
+        }
+    }
+
+    /// Returns the overrides for the supplied architecture, if there are any
+    /// # Arguments
+    /// * `arch` - The architecture to get the overrides for
+    pub fn arch_override(&self, arch: &str) -> Option<&ArchOverride> {
+        self.arch_overrides
+            .iter()
+            .flatten()
+            .find(|o| o.arch == arch)
+    }
+
+    /// Returns a copy of this packagebuild with the overrides for the supplied architecture
+    /// applied, fails if the packagebuild does not support the architecture
+    /// # Arguments
+    /// * `arch` - The architecture to build for
+    pub fn for_arch(&self, arch: &str) -> Result<PackageBuild, Error> {
+        if !self.supports_arch(arch) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "Packagebuild '{}' does not support the architecture '{arch}', only: {}",
+                    self.name,
+                    self.arch
+                        .iter()
+                        .flatten()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ));
+        }
+
+        let o = match self.arch_override(arch) {
+            Some(o) => o,
+            None => return Ok(self.clone()),
+        };
+
+        fn or<T: Clone>(over: &Option<T>, base: &Option<T>) -> Option<T> {
+            over.as_ref().or(base.as_ref()).cloned()
+        }
+
+        Ok(PackageBuild {
+            source: or(&o.source, &self.source),
+            // The checksum belongs to the source it was overridden with
+            source_sha256: match o.source {
+                Some(_) => o.source_sha256.clone(),
+                None => or(&o.source_sha256, &self.source_sha256),
+            },
+            extra_dependencies: or(&o.extra_dependencies, &self.extra_dependencies),
+            optional_dependencies: or(&o.optional_dependencies, &self.optional_dependencies),
+            build_dependencies: or(&o.build_dependencies, &self.build_dependencies),
+            cross_dependencies: or(&o.cross_dependencies, &self.cross_dependencies),
+
+            prepare: or(&o.prepare, &self.prepare),
+            build: or(&o.build, &self.build),
+            check: or(&o.check, &self.check),
+            package: or(&o.package, &self.package),
+            ..self.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const B: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+    fn deps(specs: &[&str]) -> Option<Vec<Dependency>> {
+        Some(specs.iter().map(|s| s.parse().unwrap()).collect())
+    }
+
+    fn lines(lines: &[&str]) -> Option<Vec<String>> {
+        Some(lines.iter().map(|l| l.to_string()).collect())
+    }
+
+    fn pkgbuild() -> PackageBuild {
+        let mut pkgbuild = PackageBuild::new("foo", "1.0", 0);
+        pkgbuild.arch = Some(vec!["x86_64".to_owned(), "aarch64".to_owned()]);
+        pkgbuild.source = Some("https://example.org/foo.tar.gz".to_owned());
+        pkgbuild.source_sha256 = Some(A.to_owned());
+        pkgbuild.extra_dependencies = deps(&["glibc"]);
+        pkgbuild.build_dependencies = deps(&["gcc", "make"]);
+        pkgbuild.build = lines(&["make"]);
+        pkgbuild.package = lines(&["make install"]);
+        pkgbuild
+    }
+
+    #[test]
+    fn supported_archs() {
+        let mut pkgbuild = pkgbuild();
+        assert!(pkgbuild.supports_arch("x86_64"));
+        assert!(!pkgbuild.supports_arch("riscv64"));
+        assert_eq!(
+            pkgbuild.for_arch("riscv64").unwrap_err().kind(),
+            ErrorKind::Unsupported
+        );
+
+        pkgbuild.arch = Some(vec![ARCH_ANY.to_owned()]);
+        assert!(pkgbuild.supports_arch("riscv64"));
+        pkgbuild.arch = None;
+        assert!(pkgbuild.supports_arch("riscv64"));
+    }
+
+    #[test]
+    fn without_overrides() {
+        let pkgbuild = pkgbuild();
+        assert_eq!(pkgbuild.for_arch("x86_64").unwrap(), pkgbuild);
+    }
+
+    #[test]
+    fn overrides_replace_fields() {
+        let mut pkgbuild = pkgbuild();
+        let mut o = ArchOverride::new("aarch64");
+        o.build_dependencies = deps(&["clang"]);
+        o.cross_dependencies = deps(&["glibc"]);
+        o.build = lines(&["make ARCH=arm64"]);
+        pkgbuild.arch_overrides = Some(vec![o]);
+
+        let aarch64 = pkgbuild.for_arch("aarch64").unwrap();
+        // Overridden fields are replaced, not merged
+        assert_eq!(aarch64.build_dependencies, deps(&["clang"]));
+        assert_eq!(aarch64.cross_dependencies, deps(&["glibc"]));
+        assert_eq!(aarch64.build, lines(&["make ARCH=arm64"]));
+        // The others are kept
+        assert_eq!(aarch64.extra_dependencies, pkgbuild.extra_dependencies);
+        assert_eq!(aarch64.source, pkgbuild.source);
+        assert_eq!(aarch64.source_sha256, pkgbuild.source_sha256);
+        assert_eq!(aarch64.package, pkgbuild.package);
+        assert_eq!(aarch64.check, None);
+
+        // Overrides of other architectures do not apply
+        assert_eq!(pkgbuild.for_arch("x86_64").unwrap().build, pkgbuild.build);
+    }
+
+    #[test]
+    fn overridden_source_checksums() {
+        let mut pkgbuild = pkgbuild();
+
+        // A new source does not take the checksum of the replaced one
+        let mut o = ArchOverride::new("aarch64");
+        o.source = Some("https://example.org/foo-arm.tar.gz".to_owned());
+        pkgbuild.arch_overrides = Some(vec![o.clone()]);
+        let aarch64 = pkgbuild.for_arch("aarch64").unwrap();
+        assert_eq!(aarch64.source, o.source);
+        assert_eq!(aarch64.source_sha256, None);
+
+        o.source_sha256 = Some(B.to_owned());
+        pkgbuild.arch_overrides = Some(vec![o]);
+        assert_eq!(
+            pkgbuild
+                .for_arch("aarch64")
+                .unwrap()
+                .source_sha256
+                .as_deref(),
+            Some(B)
+        );
+
+        // Only the checksum can be overridden as well
+        let mut o = ArchOverride::new("aarch64");
+        o.source_sha256 = Some(B.to_owned());
+        pkgbuild.arch_overrides = Some(vec![o]);
+        let aarch64 = pkgbuild.for_arch("aarch64").unwrap();
+        assert_eq!(aarch64.source, pkgbuild.source);
+        assert_eq!(aarch64.source_sha256.as_deref(), Some(B));
+    }
+
+    #[test]
+    fn parsed_overrides() {
+        let pkgbuild = crate::parser::parse(
+            &mut "name=foo\nversion=1.0\nreal_version=0\narch=[x86_64][aarch64]\n\
+                  build_dependencies=[gcc]\nbuild_dependencies_aarch64=[clang]\n\
+                  build={\n  make\n}\nbuild_aarch64={\n  make ARCH=arm64\n}\n"
+                .as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(pkgbuild.arch_override("x86_64"), None);
+        let aarch64 = pkgbuild.for_arch("aarch64").unwrap();
+        assert_eq!(aarch64.build_dependencies, deps(&["clang"]));
+        assert_eq!(aarch64.build, lines(&["make ARCH=arm64"]));
+        assert_eq!(
+            pkgbuild.for_arch("x86_64").unwrap().build_dependencies,
+            deps(&["gcc"])
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Formats packagebuilds to their canonical form, see `pkgbuild::parser::format()`
+//!
+//! Usage: `pkgbuild-fmt [--check] [FILE]...`
+//!
+//! The files get formatted in place, without files the packagebuild is read from stdin
+//! and written to stdout. With `--check`, nothing is written and the command fails
+//! if one of the packagebuilds is not formatted.
+
+use pkgbuild::parser::Document;
+use std::{
+    fs,
+    io::{self, Error, Read, Write},
+    process::ExitCode,
+};
+
+const USAGE: &str = "Usage: pkgbuild-fmt [--check] [FILE]...";
+
+fn main() -> ExitCode {
+    let mut check = false;
+    let mut files: Vec<String> = Vec::new();
+
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--check" => check = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if arg.starts_with('-') => {
+                eprintln!("Unknown option '{arg}'\n{USAGE}");
+                return ExitCode::from(2);
+            }
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        return match format_stdin(check) {
+            Ok(true) => ExitCode::SUCCESS,
+            Ok(false) => {
+                eprintln!("<stdin> is not formatted");
+                ExitCode::FAILURE
+            }
+            Err(e) => {
+                eprintln!("<stdin>: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let mut success = true;
+    for file in &files {
+        match format_file(file, check) {
+            Ok(true) => {}
+            Ok(false) => {
+                eprintln!("{file} is not formatted");
+                success = false;
+            }
+            Err(e) => {
+                eprintln!("{file}: {e}");
+                success = false;
+            }
+        }
+    }
+
+    if success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Returns the canonical form of the supplied packagebuild
+/// # Arguments
+/// * `contents` - The packagebuild to format
+fn format_str(contents: &str) -> Result<String, Error> {
+    Ok(contents.parse::<Document>()?.format()?.to_string())
+}
+
+/// Formats a file in place, returns false if it is not formatted in check mode
+/// # Arguments
+/// * `path` - The path of the file to format
+/// * `check` - Only check the file, don't write it
+fn format_file(path: &str, check: bool) -> Result<bool, Error> {
+    let contents = fs::read_to_string(path)?;
+    let formatted = format_str(&contents)?;
+
+    if formatted == contents {
+        return Ok(true);
+    }
+    if !check {
+        fs::write(path, formatted)?;
+    }
+
+    Ok(!check)
+}
+
+/// Formats stdin to stdout, returns false if it is not formatted in check mode
+/// # Arguments
+/// * `check` - Only check stdin, don't write to stdout
+fn format_stdin(check: bool) -> Result<bool, Error> {
+    let mut contents = String::new();
+    io::stdin().read_to_string(&mut contents)?;
+    let formatted = format_str(&contents)?;
+
+    if !check {
+        io::stdout().write_all(formatted.as_bytes())?;
+    }
+
+    Ok(formatted == contents || !check)
+}
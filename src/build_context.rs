@@ -1,24 +1,37 @@
 use crate::util::*;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 mod build;
+mod cache;
+mod git;
 mod sources;
+mod split;
 
 use leaf::error::LError;
 use leaf::error::LErrorClass;
 use leaf::Leaf;
+use nix::sched::{setns, unshare, CloneFlags};
 use sys_mount::Mount;
 use sys_mount::UnmountDrop;
 
 use crate::StdIOErrorExt;
-use crate::{mount, BuilderConfiguration, PackageBuild};
+use crate::{
+    check_name, mount, BuilderConfiguration, ChecksumMismatch, Dependency, FetcherRegistry,
+    PackageBuild, SourceResolver,
+};
 
 /// A build context with valid mounts, config and packagebuild
 #[allow(dead_code)]
 pub struct BuildContext<'a> {
-    pkgbuild: &'a PackageBuild,
+    /// The packagebuild with the overrides for the target architecture applied
+    pkgbuild: PackageBuild,
     config: &'a BuilderConfiguration,
     mounts: Vec<UnmountDrop<Mount>>,
+    /// The resolver for references in `extra_sources`
+    resolver: Option<Box<dyn SourceResolver>>,
+    /// The fetchers for the URL schemes of the sources
+    fetchers: FetcherRegistry,
 }
 
 /// All possible kinds of build context errors
@@ -27,6 +40,7 @@ pub enum BCErrorKind {
     IO(std::io::ErrorKind),
     Leaf(LErrorClass),
     ZIP(zip::result::ZipError),
+    Checksum(ChecksumMismatch),
 }
 
 /// A build context error
@@ -41,17 +55,70 @@ impl PackageBuild {
     /// Create a build context from a packagebuild
     /// * `config` - The configuration to use for the context
     /// * `leaf` - The leaf instance to use for installing packages
+    /// * `target_leaf` - The leaf instance installing the packages of the target architecture
+    ///   into the sysroot, required for cross builds with `cross_dependencies`
     pub fn build_context<'a>(
         &'a mut self,
         config: &'a BuilderConfiguration,
         leaf: &mut Leaf,
+        target_leaf: Option<&mut Leaf>,
     ) -> Result<BuildContext, BCError> {
         let mut mounts: Vec<UnmountDrop<Mount>> = Vec::new();
 
+        // Building without the template's phases would produce a broken package
+        if let Some(template) = &self.inherit {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Template '{template}' has not been resolved, load the packagebuild using a TemplateResolver"
+                ),
+            )
+            .into());
+        }
+
+        // Refuse to build for unsupported architectures and apply the overrides
+        let pkgbuild = self.for_arch(&config.arch)?;
+
+        if let Some(cross) = &config.cross {
+            if cross.target_arch() != config.arch {
+                warn!(
+                    "Cross compiling for {}, but the target architecture is {}",
+                    cross.target, config.arch
+                );
+            }
+
+            // The packages of the builder's leaf are for the wrong architecture
+            let has_cross_deps = pkgbuild
+                .cross_dependencies
+                .as_ref()
+                .is_some_and(|deps| !deps.is_empty());
+            if has_cross_deps && target_leaf.is_none() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Cross compiling for {} needs a leaf instance for {} to install the cross dependencies into the sysroot",
+                        cross.target,
+                        cross.target_arch()
+                    ),
+                )
+                .into());
+            }
+        }
+
         info!("Ensuring directories...");
         clean_dir(&config.get_overlay_upper_dir())?;
         clean_dir(&config.get_build_dir(&self))?;
         clean_dir(&config.get_target_dir(&self))?;
+        for split in self.split_packages.iter().flatten() {
+            // Split names become directory names, don't let them escape the targets directory
+            check_name(&split.name).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid split package name: {e}"),
+                )
+            })?;
+            clean_dir(&config.get_output_target_dir(self, &split.name))?;
+        }
 
         info!(
             "Installing '{}' environment packages to {}",
@@ -59,15 +126,12 @@ impl PackageBuild {
             &config.get_environment_root_dir().to_string_lossy()
         );
         leaf.config.root = Some(config.get_environment_root_dir());
-        match leaf.update() {
-            Ok(_) => {}
-            Err(v_e) => {
-                return Err(BCError::from(
-                    v_e.first().expect("At least one error").clone(),
-                ))
-            }
-        };
-        leaf.install(&config.environment.packages)?;
+        if config.offline {
+            info!("Offline mode, using the cached package lists and packages of leaf");
+        } else {
+            update(leaf)?;
+        }
+        install(leaf, config, &config.environment.packages)?;
 
         info!("Mounting overlay");
         mounts.push(
@@ -101,20 +165,245 @@ impl PackageBuild {
             .err_prepend("When mounting target directory")?,
         );
 
+        for split in self.split_packages.iter().flatten() {
+            let buildroot_dir = config.get_buildroot_split_target_dir(self, &split.name);
+            clean_dir(&buildroot_dir).err_prepend(&format!(
+                "When creating buildroot target directory of split package {}",
+                split.name
+            ))?;
+            mounts.push(
+                mount::mount_bind(
+                    &config.get_output_target_dir(self, &split.name),
+                    &buildroot_dir,
+                )
+                .err_prepend(&format!(
+                    "When mounting target directory of split package {}",
+                    split.name
+                ))?,
+            );
+        }
+
         info!("Installing build dependencies");
         leaf.config.root = Some(config.get_build_dir(self));
-        if let Some(deps) = &self.build_dependencies {
-            leaf.install(deps)?;
+        let expanded = pkgbuild.apply_build_system().expand(&HashMap::new())?;
+        if let Some(deps) = &expanded.build_dependencies {
+            let specs: Vec<String> = deps.iter().map(|d| d.to_install_spec()).collect();
+            install(leaf, config, &specs)?;
+        }
+
+        match &config.cross {
+            Some(cross) => {
+                info!(
+                    "Installing cross dependencies for {} to the sysroot",
+                    cross.target
+                );
+                let sysroot = config.get_buildroot_sysroot_dir(self);
+                clean_dir(&sysroot).err_prepend("When creating sysroot directory")?;
+
+                // Checked to be there if there are cross dependencies
+                if let (Some(deps), Some(target_leaf)) = (&expanded.cross_dependencies, target_leaf)
+                {
+                    target_leaf.config.root = Some(sysroot);
+                    if !config.offline {
+                        update(target_leaf)?;
+                    }
+                    let specs: Vec<String> = deps.iter().map(|d| d.to_install_spec()).collect();
+                    install(target_leaf, config, &specs)?;
+                }
+            }
+            None => {
+                if expanded.cross_dependencies.is_some() {
+                    debug!("Not cross compiling, skipping cross dependencies");
+                }
+            }
         }
 
         Ok(BuildContext {
-            pkgbuild: self,
+            pkgbuild,
             config: config,
             mounts,
+            resolver: None,
+            fetchers: FetcherRegistry::new(),
         })
     }
 }
 
+/// Updates the package lists of leaf
+/// # Arguments
+/// * `leaf` - The leaf instance to update
+fn update(leaf: &mut Leaf) -> Result<(), BCError> {
+    match leaf.update() {
+        Ok(_) => Ok(()),
+        Err(v_e) => Err(BCError::from(
+            v_e.first().expect("At least one error").clone(),
+        )),
+    }
+}
+
+/// Installs packages using leaf.
+///
+/// In offline mode, fails before installing if a package is not in the leaf cache
+/// in a version satisfying its constraint. As that can't cover the dependencies leaf
+/// resolves itself, leaf then runs without network access, so it fails instead of
+/// downloading anything
+/// # Arguments
+/// * `leaf` - The leaf instance to install with
+/// * `config` - The builder configuration
+/// * `specs` - The packages to install, optionally with version constraints
+fn install(
+    leaf: &mut Leaf,
+    config: &BuilderConfiguration,
+    specs: &Vec<String>,
+) -> Result<(), BCError> {
+    if !config.offline {
+        leaf.install(specs)?;
+        return Ok(());
+    }
+
+    check_offline_cache(&config.get_leaf_cache_dir(), specs)?;
+    without_network(|| leaf.install(specs))
+        .err_prepend("When installing packages in offline mode")??;
+
+    Ok(())
+}
+
+/// Checks that every package to install has an archive in the leaf cache
+/// whose version satisfies the constraint of the package
+/// # Arguments
+/// * `cache_dir` - The leaf cache
+/// * `specs` - The packages to install, optionally with version constraints
+fn check_offline_cache(
+    cache_dir: &std::path::Path,
+    specs: &[String],
+) -> Result<(), std::io::Error> {
+    let mut cached: Vec<String> = Vec::new();
+    collect_file_names(cache_dir, &mut cached)
+        .err_prepend("When reading leaf cache for offline mode")?;
+
+    let missing: Vec<&str> = specs
+        .iter()
+        .filter(|spec| {
+            let dependency = match spec.parse::<Dependency>() {
+                Ok(dependency) => dependency,
+                Err(_) => Dependency::new(spec),
+            };
+
+            !cached.iter().any(|file| {
+                cached_package_versions(file, &dependency.name)
+                    .iter()
+                    .any(|version| dependency.matches(version))
+            })
+        })
+        .map(|spec| spec.as_str())
+        .collect();
+
+    if !missing.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "Offline mode is enabled, but the packages {} are not in the leaf cache {}",
+                missing.join(", "),
+                cache_dir.to_string_lossy()
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns the versions a file in the leaf cache could be a package archive of the supplied
+/// package in: `<name>-<version>[-<real_version>][.<extension>...]`, where the version starts
+/// with a digit. Without a package archive of the package, there are none.
+///
+/// As the real version can't be told apart from the version, both with and without it are returned
+/// # Arguments
+/// * `file` - The name of the file in the cache
+/// * `name` - The name of the package
+fn cached_package_versions(file: &str, name: &str) -> Vec<String> {
+    let mut version = match file
+        .strip_prefix(name)
+        .and_then(|rest| rest.strip_prefix('-'))
+    {
+        Some(version) if version.starts_with(|c: char| c.is_ascii_digit()) => version,
+        _ => return Vec::new(),
+    };
+
+    // Strip the extensions, the components of the version start with a digit
+    while let Some((rest, extension)) = version.rsplit_once('.') {
+        if extension.starts_with(|c: char| c.is_ascii_digit()) {
+            break;
+        }
+        version = rest;
+    }
+
+    let mut versions = vec![version.to_owned()];
+    if let Some((without_real, real)) = version.rsplit_once('-') {
+        if !real.is_empty() && real.chars().all(|c| c.is_ascii_digit()) {
+            versions.push(without_real.to_owned());
+        }
+    }
+
+    versions
+}
+
+/// Runs the supplied function in a new, empty network namespace so it can't access the network.
+///
+/// Only the calling thread is moved, it returns to its network namespace afterwards
+/// # Arguments
+/// * `f` - The function to run
+fn without_network<T, F: FnOnce() -> T>(f: F) -> Result<T, std::io::Error> {
+    let original = std::fs::File::open("/proc/thread-self/ns/net")
+        .err_prepend("When opening the network namespace")?;
+
+    unshare(CloneFlags::CLONE_NEWNET)
+        .map_err(std::io::Error::from)
+        .err_prepend("When creating a network namespace without network access")?;
+
+    let res = f();
+
+    setns(&original, CloneFlags::CLONE_NEWNET)
+        .map_err(std::io::Error::from)
+        .err_prepend("When restoring the network namespace")?;
+
+    Ok(res)
+}
+
+/// Collects the names of all files below the supplied directory, recursively
+/// # Arguments
+/// * `dir` - The directory to search, a missing one has no files
+/// * `names` - The vector to push the names to
+fn collect_file_names(
+    dir: &std::path::Path,
+    names: &mut Vec<String>,
+) -> Result<(), std::io::Error> {
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            collect_file_names(&entry.path(), names)?;
+        } else {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+
+    Ok(())
+}
+
+impl BCError {
+    /// Returns the kind of the error
+    pub fn kind(&self) -> &BCErrorKind {
+        &self.kind
+    }
+
+    /// Returns the message of the error
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
 impl From<std::io::Error> for BCError {
     fn from(value: std::io::Error) -> Self {
         Self {
@@ -141,3 +430,84 @@ impl From<zip::result::ZipError> for BCError {
         }
     }
 }
+
+impl From<ChecksumMismatch> for BCError {
+    fn from(value: ChecksumMismatch) -> Self {
+        Self {
+            message: value.to_string(),
+            kind: BCErrorKind::Checksum(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_package_names() {
+        assert_eq!(
+            cached_package_versions("foo-1.0-2.lfpkg", "foo"),
+            vec!["1.0-2", "1.0"]
+        );
+        assert_eq!(
+            cached_package_versions("foo-dev-1.0.tar.xz", "foo-dev"),
+            vec!["1.0"]
+        );
+        assert!(cached_package_versions("foo-dev-1.0-2.lfpkg", "foo").is_empty());
+        assert!(cached_package_versions("foobar-1.0.lfpkg", "foo").is_empty());
+        assert!(cached_package_versions("foo.lfpkg", "foo").is_empty());
+    }
+
+    #[test]
+    fn offline_cache_refusal() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("pkgbuild-leaf-cache-{}", std::process::id()));
+        std::fs::create_dir_all(cache_dir.join("packages")).unwrap();
+        std::fs::write(cache_dir.join("packages/foo-2.0-1.lfpkg"), "").unwrap();
+        std::fs::write(cache_dir.join("bar-1.2.tar.xz"), "").unwrap();
+
+        let check = |specs: &[&str]| {
+            let specs: Vec<String> = specs.iter().map(|s| s.to_string()).collect();
+            check_offline_cache(&cache_dir, &specs)
+        };
+
+        assert!(check(&["foo", "foo>=2", "foo=2.0", "bar<2", "bar=1.2*"]).is_ok());
+
+        // `foo-2` does not satisfy `foo>=3`
+        let err = check(&["foo>=3", "bar"]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("foo>=3"), "{err}");
+        assert!(!err.to_string().contains("bar"), "{err}");
+
+        assert!(check(&["baz"]).is_err());
+        assert!(check(&["foo-dev"]).is_err());
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+
+        // Without a cache, nothing can be installed
+        assert!(check(&["foo"]).is_err());
+        assert!(check(&[]).is_ok());
+    }
+
+    #[test]
+    fn isolated_from_network() {
+        let namespace = || std::fs::read_link("/proc/thread-self/ns/net").unwrap();
+        let before = namespace();
+
+        let isolated = without_network(|| {
+            (
+                namespace(),
+                std::net::UdpSocket::bind("127.0.0.1:0")
+                    .and_then(|s| s.connect("1.1.1.1:53"))
+                    .is_ok(),
+            )
+        });
+
+        // Creating network namespaces needs privileges the builder has, but tests may not
+        if let Ok((inside, connected)) = isolated {
+            assert_ne!(inside, before);
+            assert!(!connected);
+        }
+        assert_eq!(namespace(), before);
+    }
+}
@@ -3,6 +3,7 @@ use std::process::ExitStatus;
 use std::sync::{Arc, Mutex};
 use std::{fs::File, process::Command};
 
+use crate::util::shell_quote;
 use crate::{BCError, StdIOErrorExt};
 
 use super::BuildContext;
@@ -10,10 +11,13 @@ use super::BuildContext;
 impl<'a> BuildContext<'a> {
     /// Build the package using the context
     pub fn build_package(&mut self) -> Result<(), BCError> {
-        match &self.pkgbuild.prepare {
+        // Fill in the phases the packagebuild leaves to its build system
+        let pkgbuild = self.pkgbuild.apply_build_system();
+
+        match &pkgbuild.prepare {
             Some(script) => {
                 info!("PREPARE script exists, running...");
-                let status = self.run_script(script, "prepare.sh")?;
+                let status = self.run_script(script, "prepare.sh", "/target")?;
                 info!("PREPARE script is done: SUCCESS: {}", status.success())
             }
             None => {
@@ -21,10 +25,10 @@ impl<'a> BuildContext<'a> {
             }
         }
 
-        match &self.pkgbuild.build {
+        match &pkgbuild.build {
             Some(script) => {
                 info!("BUILD script exists, running...");
-                let status = self.run_script(script, "build.sh")?;
+                let status = self.run_script(script, "build.sh", "/target")?;
                 info!("BUILD script is done: SUCCESS: {}", status.success())
             }
             None => {
@@ -32,10 +36,10 @@ impl<'a> BuildContext<'a> {
             }
         }
 
-        match &self.pkgbuild.check {
+        match &pkgbuild.check {
             Some(script) => {
                 info!("CHECK script exists, running...");
-                let status = self.run_script(script, "check.sh")?;
+                let status = self.run_script(script, "check.sh", "/target")?;
                 info!("CHECK script is done: SUCCESS: {}", status.success())
             }
             None => {
@@ -43,10 +47,10 @@ impl<'a> BuildContext<'a> {
             }
         }
 
-        match &self.pkgbuild.package {
+        match &pkgbuild.package {
             Some(script) => {
                 info!("PACKAGE script exists, running...");
-                let status = self.run_script(script, "package.sh")?;
+                let status = self.run_script(script, "package.sh", "/target")?;
                 info!("PACKAGE script is done: SUCCESS: {}", status.success())
             }
             None => {
@@ -54,6 +58,10 @@ impl<'a> BuildContext<'a> {
             }
         }
 
+        for split in pkgbuild.split_packages.iter().flatten() {
+            self.package_split(split)?;
+        }
+
         Ok(())
     }
 
@@ -61,14 +69,16 @@ impl<'a> BuildContext<'a> {
     /// # Arguments
     /// * `script` - The lines of the script
     /// * `script_name` - The name the script file should have
-    fn run_script(
+    /// * `pkg_root` - The target directory of the package within the build root (`PKG_ROOT`)
+    pub(super) fn run_script(
         &mut self,
         script: &Vec<String>,
         script_name: &str,
+        pkg_root: &str,
     ) -> Result<ExitStatus, BCError> {
         let path = self
             .config
-            .get_buildroot_build_dir(self.pkgbuild)
+            .get_buildroot_build_dir(&self.pkgbuild)
             .join(script_name);
 
         let mut output = File::create(&path).err_prepend("When creating build script")?;
@@ -77,22 +87,60 @@ impl<'a> BuildContext<'a> {
             writeln!(output, "{}", line).err_prepend("When populating build script")?;
         }
 
+        // Export the user-defined variables of the packagebuild
+        let variables = self.pkgbuild.resolved_variables()?;
+        let mut exports = String::new();
+        for variable in self.pkgbuild.vars.iter().flatten() {
+            exports.push_str(&format!(
+                "export {}={} &&\n",
+                variable.name,
+                shell_quote(&variables[&variable.name])
+            ));
+        }
+
+        // Export the toolchain of cross builds
+        if let Some(cross) = &self.config.cross {
+            let toolchain = [
+                ("CBUILD", shell_quote(&cross.host)),
+                ("CHOST", shell_quote(&cross.target)),
+                ("CC", "\"$CHOST-gcc\"".to_owned()),
+                ("CXX", "\"$CHOST-g++\"".to_owned()),
+                ("PKG_SYSROOT", "/sysroot".to_owned()),
+                ("PKG_CONFIG_SYSROOT_DIR", "$PKG_SYSROOT".to_owned()),
+                (
+                    "PKG_CONFIG_LIBDIR",
+                    "$PKG_SYSROOT/usr/lib/pkgconfig:$PKG_SYSROOT/usr/share/pkgconfig".to_owned(),
+                ),
+            ];
+            for (name, value) in toolchain {
+                exports.push_str(&format!("export {name}={value} &&\n"));
+            }
+        }
+
         let command_string = format!(
             "
             set -e &&
             export PKG_NAME={} &&
             export PKG_VERSION={} &&
-            export PKG_ROOT=/target &&
+            export PKG_ROOT={} &&
             export PKG_INSTALL_DIR=$PKG_ROOT/data &&
+            export PKG_MAIN_INSTALL_DIR=/target/data &&
+            export CARCH={} &&
+            {}
             cd build &&
             /bin/sh /build/{}
         ",
-            self.pkgbuild.name, self.pkgbuild.version, script_name
+            self.pkgbuild.name,
+            self.pkgbuild.version,
+            pkg_root,
+            shell_quote(&self.config.arch),
+            exports,
+            script_name
         );
 
         let mut command = Command::new("/usr/bin/chroot");
         let child = command
-            .arg(self.config.get_build_dir(self.pkgbuild))
+            .arg(self.config.get_build_dir(&self.pkgbuild))
             .args(["/bin/sh", "-c", &command_string])
             .spawn()
             .unwrap();
@@ -0,0 +1,344 @@
+use super::BuildContext;
+use crate::{sha256, verify_sha256, BCError, SourceFetcher, StdIOErrorExt};
+use std::{
+    fs::{self, File},
+    io::{Error, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+impl<'a> BuildContext<'a> {
+    /// Fetches a source to the supplied path using the fetcher for its scheme.
+    ///
+    /// Sources of cacheable fetchers go through the source cache, others,
+    /// such as local files, are fetched directly and are available in offline mode
+    /// # Arguments
+    /// * `url` - The URL to fetch the source from
+    /// * `checksum` - The expected SHA-256 checksum of the source
+    /// * `dst` - The path to place the source at
+    pub(super) fn fetch_source(
+        &self,
+        url: &str,
+        checksum: Option<&str>,
+        dst: &Path,
+    ) -> Result<(), BCError> {
+        let fetcher = self.fetchers.get(url)?;
+
+        if dst.exists() {
+            fs::remove_file(dst)?;
+        }
+
+        if !fetcher.cacheable() {
+            return fetch_verified(fetcher, url, checksum, dst);
+        }
+
+        let cached = self.fetch_cached(fetcher, url, checksum)?;
+        place_file(&cached, dst)?;
+
+        Ok(())
+    }
+
+    /// Returns the path of a source in the source cache, fetching it on a miss.
+    ///
+    /// Sources with a checksum are addressed by it, so the same file is only stored once,
+    /// cache hits are verified again. Others are addressed by their URL and fetched again
+    /// on every build, their cache entry is only used in offline mode. Sources are fetched
+    /// to a temporary file that is verified and then renamed atomically, so the cache never
+    /// contains partial or corrupt files.
+    /// # Arguments
+    /// * `fetcher` - The fetcher to fetch the source with
+    /// * `url` - The URL to fetch the source from
+    /// * `checksum` - The expected SHA-256 checksum of the source
+    fn fetch_cached(
+        &self,
+        fetcher: &dyn SourceFetcher,
+        url: &str,
+        checksum: Option<&str>,
+    ) -> Result<PathBuf, BCError> {
+        let cache_dir = self.config.get_source_cache_dir();
+        fs::create_dir_all(&cache_dir).err_prepend("When creating source cache directory")?;
+
+        let key = match checksum {
+            Some(checksum) => format!("sha256-{}", checksum.to_lowercase()),
+            None => format!("url-{}", sha256(&mut url.as_bytes())?),
+        };
+        let path = cache_dir.join(&key);
+
+        if path.exists() {
+            match checksum {
+                Some(expected) => match verify_sha256(url, &mut File::open(&path)?, expected) {
+                    Ok(()) => {
+                        info!("Source cache hit for {}", url);
+                        return Ok(path);
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Removing corrupt source cache entry for {}: {}",
+                            url,
+                            e.message()
+                        );
+                        fs::remove_file(&path)
+                            .err_prepend("When removing corrupt source cache entry")?;
+                    }
+                },
+                None if self.config.offline => {
+                    warn!(
+                        "Using cached source {} without checksum, it may be outdated",
+                        url
+                    );
+                    return Ok(path);
+                }
+                // Without a checksum, the cache can't know if the file changed upstream
+                None => info!("Source {} has no checksum, refreshing its cache entry", url),
+            }
+        }
+
+        if self.config.offline {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Source {url} is not in the source cache and offline mode is enabled"),
+            )
+            .into());
+        }
+        info!("Source cache miss for {}, fetching", url);
+
+        let tmp_path = cache_dir.join(format!("{key}.part-{}", std::process::id()));
+        let res = fetch_verified(fetcher, url, checksum, &tmp_path).and_then(|_| {
+            fs::rename(&tmp_path, &path)
+                .err_prepend("When moving source into the source cache")
+                .map_err(BCError::from)
+        });
+
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        res?;
+
+        Ok(path)
+    }
+}
+
+/// Fetches a file and verifies its checksum, if there is one
+/// # Arguments
+/// * `fetcher` - The fetcher to fetch the file with
+/// * `url` - The URL to fetch
+/// * `checksum` - The expected SHA-256 checksum of the file
+/// * `path` - The path to fetch to
+fn fetch_verified(
+    fetcher: &dyn SourceFetcher,
+    url: &str,
+    checksum: Option<&str>,
+    path: &Path,
+) -> Result<(), BCError> {
+    let mut file = File::create(path).err_prepend(&format!(
+        "When creating source file {}",
+        path.to_string_lossy()
+    ))?;
+
+    fetcher.fetch(url, &mut file)?;
+    file.flush()?;
+
+    match checksum {
+        Some(expected) => {
+            info!("Verifying checksum of {}", url);
+            verify_sha256(url, &mut File::open(path)?, expected)
+        }
+        None => {
+            warn!("Source {} has no checksum, skipping verification", url);
+            Ok(())
+        }
+    }
+}
+
+/// Places a file from the source cache at the supplied path: Hardlinks it if possible
+/// and copies it otherwise, such as across filesystems
+/// # Arguments
+/// * `cached` - The file in the cache
+/// * `dst` - The path to place the file at
+fn place_file(cached: &Path, dst: &Path) -> Result<(), Error> {
+    if fs::hard_link(cached, dst).is_err() {
+        fs::copy(cached, dst).err_prepend(&format!(
+            "When copying {} to {}",
+            cached.to_string_lossy(),
+            dst.to_string_lossy()
+        ))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        BuildEnvironment, BuilderConfiguration, FetcherRegistry, FileFetcher, PackageBuild,
+    };
+    use std::os::unix::fs::MetadataExt;
+
+    /// Serves the files below a directory as `test://<name>` sources that get cached
+    struct CachedFiles(FileFetcher);
+
+    impl SourceFetcher for CachedFiles {
+        fn fetch(&self, url: &str, output: &mut dyn Write) -> Result<(), BCError> {
+            self.0.fetch(url.trim_start_matches("test://"), output)
+        }
+    }
+
+    fn root(test: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("pkgbuild-cache-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("files")).unwrap();
+        fs::write(root.join("files/a.tar"), "contents").unwrap();
+        root
+    }
+
+    fn config(root: &Path, offline: bool) -> BuilderConfiguration {
+        BuilderConfiguration {
+            root: root.to_owned(),
+            environment: BuildEnvironment::new("base", vec![]),
+            arch: "x86_64".to_owned(),
+            offline,
+            cross: None,
+        }
+    }
+
+    fn context<'a>(root: &Path, config: &'a BuilderConfiguration) -> BuildContext<'a> {
+        let mut fetchers = FetcherRegistry::new();
+        fetchers.register(
+            "test",
+            Box::new(CachedFiles(FileFetcher::new(Some(&root.join("files"))))),
+        );
+
+        BuildContext {
+            pkgbuild: PackageBuild::new("foo", "1.0", 0),
+            config,
+            mounts: Vec::new(),
+            resolver: None,
+            fetchers,
+        }
+    }
+
+    fn checksum(contents: &str) -> String {
+        sha256(&mut contents.as_bytes()).unwrap()
+    }
+
+    fn cache_entries(config: &BuilderConfiguration) -> Vec<String> {
+        let mut entries: Vec<String> = fs::read_dir(config.get_source_cache_dir())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn cache_keys() {
+        let root = root("keys");
+        let config = config(&root, false);
+        let context = context(&root, &config);
+        let dst = root.join("a.tar");
+
+        let sum = checksum("contents");
+        context
+            .fetch_source("test://a.tar", Some(&sum.to_uppercase()), &dst)
+            .unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "contents");
+
+        context.fetch_source("test://a.tar", None, &dst).unwrap();
+        let url_key = format!("url-{}", checksum("test://a.tar"));
+
+        let mut expected = vec![format!("sha256-{sum}"), url_key];
+        expected.sort();
+        assert_eq!(cache_entries(&config), expected);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn corrupt_entries_are_fetched_again() {
+        let root = root("corrupt");
+        let config = config(&root, false);
+        let context = context(&root, &config);
+
+        let sum = checksum("contents");
+        let entry = config.get_source_cache_dir().join(format!("sha256-{sum}"));
+        fs::create_dir_all(config.get_source_cache_dir()).unwrap();
+        fs::write(&entry, "corrupt").unwrap();
+
+        let dst = root.join("a.tar");
+        context
+            .fetch_source("test://a.tar", Some(&sum), &dst)
+            .unwrap();
+        assert_eq!(fs::read_to_string(&entry).unwrap(), "contents");
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "contents");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn failed_fetches_leave_no_entries() {
+        let root = root("failed");
+        let config = config(&root, false);
+        let context = context(&root, &config);
+        let dst = root.join("a.tar");
+
+        // The checksum of other contents, the fetched file gets rejected
+        let res = context.fetch_source("test://a.tar", Some(&checksum("other")), &dst);
+        assert!(res.is_err());
+        assert!(context
+            .fetch_source("test://missing.tar", None, &dst)
+            .is_err());
+        assert_eq!(cache_entries(&config), Vec::<String>::new());
+
+        // Successful fetches only leave the renamed entry behind
+        context.fetch_source("test://a.tar", None, &dst).unwrap();
+        assert!(cache_entries(&config)
+            .iter()
+            .all(|e| e.starts_with("url-") && !e.contains(".part")));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn offline_cache() {
+        let root = root("offline");
+        let online = config(&root, false);
+        context(&root, &online)
+            .fetch_source("test://a.tar", None, &root.join("a.tar"))
+            .unwrap();
+        fs::remove_file(root.join("files/a.tar")).unwrap();
+
+        let offline = config(&root, true);
+        let context = context(&root, &offline);
+        let dst = root.join("b.tar");
+        context.fetch_source("test://a.tar", None, &dst).unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "contents");
+
+        let err = context
+            .fetch_source("test://other.tar", None, &dst)
+            .unwrap_err();
+        assert!(format!("{err:?}").contains("offline"), "{err:?}");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn place_files() {
+        let root = root("place");
+        let cached = root.join("files/a.tar");
+
+        let linked = root.join("linked");
+        place_file(&cached, &linked).unwrap();
+        assert_eq!(
+            fs::metadata(&linked).unwrap().ino(),
+            fs::metadata(&cached).unwrap().ino()
+        );
+
+        // Linking fails if the destination exists, copying replaces it
+        let copied = root.join("copied");
+        fs::write(&copied, "old").unwrap();
+        place_file(&cached, &copied).unwrap();
+        assert_eq!(fs::read_to_string(&copied).unwrap(), "contents");
+        assert_ne!(
+            fs::metadata(&copied).unwrap().ino(),
+            fs::metadata(&cached).unwrap().ino()
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
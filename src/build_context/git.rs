@@ -0,0 +1,373 @@
+use super::BuildContext;
+use crate::{sha256, BCError, GitRevision, Source, StdIOErrorExt};
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+impl<'a> BuildContext<'a> {
+    /// Checks out a git repository source into the build directory.
+    ///
+    /// The repository is kept as a bare clone in the git cache and updated on every build,
+    /// unless the requested commit is already there or offline mode is enabled
+    /// # Arguments
+    /// * `source` - The expanded git source to prepare
+    /// * `url` - The URL of the repository, without the `git+` prefix
+    /// * `name` - The name of the directory to check the repository out to
+    pub(super) fn prepare_git_source(
+        &self,
+        source: &Source,
+        url: &str,
+        name: &str,
+    ) -> Result<(), BCError> {
+        let revision = source.git_revision();
+        let mirror = self.fetch_git_mirror(url, &revision)?;
+
+        let dst_path = self
+            .config
+            .get_buildroot_build_dir(&self.pkgbuild)
+            .join(name);
+
+        info!(
+            "Checking out {} of {} to {}",
+            revision,
+            url,
+            dst_path.to_string_lossy()
+        );
+
+        if dst_path.exists() {
+            fs::remove_dir_all(&dst_path).err_prepend(&format!(
+                "When removing old checkout {}",
+                dst_path.to_string_lossy()
+            ))?;
+        }
+
+        git(
+            None,
+            &[
+                "clone".as_ref(),
+                "--quiet".as_ref(),
+                "--no-checkout".as_ref(),
+                "--".as_ref(),
+                mirror.as_os_str(),
+                dst_path.as_os_str(),
+            ],
+        )?;
+
+        let rev = match &revision {
+            GitRevision::Default => "HEAD".to_owned(),
+            GitRevision::Tag(tag) => format!("refs/tags/{tag}^{{commit}}"),
+            GitRevision::Commit(commit) => format!("{commit}^{{commit}}"),
+            GitRevision::Branch(branch) => format!("refs/remotes/origin/{branch}"),
+        };
+        git(
+            Some(&dst_path),
+            &[
+                "checkout".as_ref(),
+                "--quiet".as_ref(),
+                "--detach".as_ref(),
+                rev.as_ref(),
+            ],
+        )?;
+
+        let head = git(Some(&dst_path), &["rev-parse".as_ref(), "HEAD".as_ref()])?;
+        if let GitRevision::Commit(commit) = &revision {
+            if head != commit.to_lowercase() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Checked out commit {head} of {url}, expected {commit}"),
+                )
+                .into());
+            }
+        }
+        info!("Checked out {} at commit {}", url, head);
+
+        Ok(())
+    }
+
+    /// Returns the path of the bare clone of a git repository in the git cache,
+    /// cloning or updating it if needed
+    /// # Arguments
+    /// * `url` - The URL of the repository
+    /// * `revision` - The revision that is going to be checked out
+    fn fetch_git_mirror(&self, url: &str, revision: &GitRevision) -> Result<PathBuf, BCError> {
+        let cache_dir = self.config.get_git_cache_dir();
+        fs::create_dir_all(&cache_dir).err_prepend("When creating git cache directory")?;
+
+        let key = sha256(&mut url.as_bytes())?;
+        let path = cache_dir.join(&key);
+
+        if path.exists() {
+            let has_commit = match revision {
+                GitRevision::Commit(commit) => git(
+                    Some(&path),
+                    &[
+                        "cat-file".as_ref(),
+                        "-e".as_ref(),
+                        format!("{commit}^{{commit}}").as_ref(),
+                    ],
+                )
+                .is_ok(),
+                _ => false,
+            };
+
+            if has_commit {
+                info!("Git cache has the requested commit of {}", url);
+            } else if self.config.offline {
+                warn!("Offline mode is enabled, using cached clone of {}", url);
+            } else {
+                info!("Updating cached clone of {}", url);
+                git(
+                    Some(&path),
+                    &[
+                        "fetch".as_ref(),
+                        "--quiet".as_ref(),
+                        "--prune".as_ref(),
+                        "--tags".as_ref(),
+                        "origin".as_ref(),
+                    ],
+                )?;
+            }
+
+            return Ok(path);
+        }
+
+        if self.config.offline {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Git repository {url} is not in the git cache and offline mode is enabled"),
+            )
+            .into());
+        }
+        info!("Cloning {}", url);
+
+        // Clone to a temporary directory first, so the cache never contains partial clones
+        let tmp_path = cache_dir.join(format!("{key}.part-{}", std::process::id()));
+        let res = git(
+            None,
+            &[
+                "clone".as_ref(),
+                "--quiet".as_ref(),
+                "--mirror".as_ref(),
+                "--".as_ref(),
+                url.as_ref(),
+                tmp_path.as_os_str(),
+            ],
+        )
+        .and_then(|_| {
+            fs::rename(&tmp_path, &path)
+                .err_prepend("When moving clone into the git cache")
+                .map_err(BCError::from)
+        });
+
+        if res.is_err() {
+            let _ = fs::remove_dir_all(&tmp_path);
+        }
+        res?;
+
+        Ok(path)
+    }
+}
+
+/// Runs git with the supplied arguments and returns its trimmed output
+/// # Arguments
+/// * `dir` - The directory to run git in, the current one if `None`
+/// * `args` - The arguments to pass to git
+fn git(dir: Option<&Path>, args: &[&std::ffi::OsStr]) -> Result<String, BCError> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+
+    let output = command
+        .args(args)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .err_prepend("When running git")?;
+
+    if !output.status.success() {
+        return Err(Error::other(format!(
+            "git {} failed: {}",
+            args.iter()
+                .map(|a| a.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BuildEnvironment, BuilderConfiguration, FetcherRegistry, PackageBuild};
+
+    /// A bare repository with an annotated tag `v1` on its first commit and a second commit
+    struct Repository {
+        root: PathBuf,
+        url: String,
+        first: String,
+        second: String,
+        tag: String,
+    }
+
+    fn repository(test: &str) -> Repository {
+        let root = std::env::temp_dir().join(format!("pkgbuild-git-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let work = root.join("work");
+        fs::create_dir_all(&work).unwrap();
+
+        let run = |args: &[&str]| {
+            let mut command = vec!["-c", "user.name=test", "-c", "user.email=test@example.org"];
+            command.extend(args);
+            let args: Vec<&std::ffi::OsStr> = command.iter().map(|a| a.as_ref()).collect();
+            git(Some(&work), &args).unwrap()
+        };
+
+        run(&["init", "--quiet", "--initial-branch=main"]);
+        fs::write(work.join("version"), "1").unwrap();
+        run(&["add", "version"]);
+        run(&["commit", "--quiet", "-m", "First"]);
+        run(&["tag", "-a", "v1", "-m", "Version 1"]);
+        let first = run(&["rev-parse", "HEAD"]);
+        let tag = run(&["rev-parse", "v1"]);
+        fs::write(work.join("version"), "2").unwrap();
+        run(&["commit", "--quiet", "-am", "Second"]);
+        let second = run(&["rev-parse", "HEAD"]);
+
+        let bare = root.join("bare.git");
+        git(
+            None,
+            &[
+                "clone".as_ref(),
+                "--quiet".as_ref(),
+                "--bare".as_ref(),
+                work.as_os_str(),
+                bare.as_os_str(),
+            ],
+        )
+        .unwrap();
+
+        Repository {
+            url: format!("file://{}", bare.to_string_lossy()),
+            root,
+            first,
+            second,
+            tag,
+        }
+    }
+
+    fn config(root: &Path, offline: bool) -> BuilderConfiguration {
+        BuilderConfiguration {
+            root: root.join("builder"),
+            environment: BuildEnvironment::new("base", vec![]),
+            arch: "x86_64".to_owned(),
+            offline,
+            cross: None,
+        }
+    }
+
+    fn context(config: &BuilderConfiguration) -> BuildContext<'_> {
+        BuildContext {
+            pkgbuild: PackageBuild::new("foo", "1.0", 0),
+            config,
+            mounts: Vec::new(),
+            resolver: None,
+            fetchers: FetcherRegistry::new(),
+        }
+    }
+
+    /// Checks out the repository at the supplied revision and returns the checked out version
+    fn checkout(
+        context: &BuildContext,
+        repository: &Repository,
+        revision: &str,
+    ) -> Result<String, BCError> {
+        let source: Source = format!("git+{}{revision}", repository.url).parse()?;
+        context.prepare_git_source(&source, &repository.url, "checkout")?;
+
+        let dir = context
+            .config
+            .get_buildroot_build_dir(&context.pkgbuild)
+            .join("checkout");
+        Ok(fs::read_to_string(dir.join("version"))?)
+    }
+
+    #[test]
+    fn checkout_revisions() {
+        let repository = repository("revisions");
+        let config = config(&repository.root, false);
+        let context = context(&config);
+
+        assert_eq!(checkout(&context, &repository, "").unwrap(), "2");
+        assert_eq!(checkout(&context, &repository, "#tag=v1").unwrap(), "1");
+        assert_eq!(
+            checkout(&context, &repository, "#branch=main").unwrap(),
+            "2"
+        );
+        let commit = format!("#commit={}", repository.first);
+        assert_eq!(checkout(&context, &repository, &commit).unwrap(), "1");
+        let commit = format!("#commit={}", repository.second.to_uppercase());
+        assert_eq!(checkout(&context, &repository, &commit).unwrap(), "2");
+
+        // One bare clone per repository, no partial ones
+        let entries: Vec<String> = fs::read_dir(config.get_git_cache_dir())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            entries,
+            vec![sha256(&mut repository.url.as_bytes()).unwrap()]
+        );
+        fs::remove_dir_all(&repository.root).unwrap();
+    }
+
+    #[test]
+    fn commit_mismatch() {
+        let repository = repository("mismatch");
+        let config = config(&repository.root, false);
+        let context = context(&config);
+
+        // The tag object peels to the commit it tags, which is not the requested hash
+        let err = checkout(
+            &context,
+            &repository,
+            &format!("#commit={}", repository.tag),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            crate::BCErrorKind::IO(ErrorKind::InvalidData)
+        ));
+        assert!(format!("{err:?}").contains(&repository.first), "{err:?}");
+
+        let missing = "0".repeat(40);
+        assert!(checkout(&context, &repository, &format!("#commit={missing}")).is_err());
+        fs::remove_dir_all(&repository.root).unwrap();
+    }
+
+    #[test]
+    fn offline_clones() {
+        let repository = repository("offline");
+        let online = config(&repository.root, false);
+        checkout(&context(&online), &repository, "#tag=v1").unwrap();
+
+        // Offline, the cached clone is used without updating it
+        fs::remove_dir_all(repository.root.join("bare.git")).unwrap();
+        let offline = config(&repository.root, true);
+        let context = context(&offline);
+        assert_eq!(checkout(&context, &repository, "").unwrap(), "2");
+
+        let other = Repository {
+            url: format!("{}-other", repository.url),
+            ..repository
+        };
+        assert!(checkout(&context, &other, "").is_err());
+        fs::remove_dir_all(&other.root).unwrap();
+    }
+}
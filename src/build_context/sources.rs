@@ -1,36 +1,119 @@
 use super::BuildContext;
-use crate::{BCError, StdIOErrorExt};
+use crate::{
+    expand_str, BCError, Source, SourceFetcher, SourceResolver, StdIOErrorExt, CHECKSUM_SKIP,
+};
 use flate2::read::GzDecoder;
-use leaf::download;
 use std::{
     fs::File,
-    io::{Read, Seek, Write},
+    io::{Error, ErrorKind, Read, Seek},
 };
 use tar::Archive;
 use xz::read::XzDecoder;
 
 impl<'a> BuildContext<'a> {
+    /// Sets the resolver for the references in `extra_sources`
+    /// # Arguments
+    /// * `resolver` - The resolver to use
+    pub fn set_source_resolver(&mut self, resolver: Box<dyn SourceResolver>) {
+        self.resolver = Some(resolver);
+    }
+
+    /// Registers a fetcher for sources with the supplied URL scheme,
+    /// replacing the built-in one if there is one
+    /// # Arguments
+    /// * `scheme` - The scheme to register the fetcher for, such as `s3`
+    /// * `fetcher` - The fetcher to use for the scheme
+    pub fn register_fetcher(&mut self, scheme: &str, fetcher: Box<dyn SourceFetcher>) {
+        self.fetchers.register(scheme, fetcher);
+    }
+
     /// Prepares all the source files for operation
     pub fn prepare_sources(&mut self) -> Result<(), BCError> {
+        let variables = self.pkgbuild.resolved_variables()?;
+
         // Check if there is a source file
-        if let Some(url) = &self.pkgbuild.source {
-            self.prepare_main_source(&url)?;
+        if let Some(source) = self.pkgbuild.source.clone() {
+            let source: Source = expand_str(&source, &variables)?.parse()?;
+            let checksum = self.pkgbuild.source_sha256.clone();
+            self.prepare_source(&source, checksum.as_deref())?;
         }
+
+        let extra_sources = self.pkgbuild.extra_sources.clone().unwrap_or_default();
+        for (i, source) in extra_sources.iter().enumerate() {
+            let source: Source = expand_str(source, &variables)?.parse()?;
+            let checksum = self
+                .pkgbuild
+                .extra_sources_sha256
+                .as_ref()
+                .and_then(|checksums| checksums.get(i))
+                .filter(|checksum| *checksum != CHECKSUM_SKIP)
+                .cloned();
+            self.prepare_source(&source, checksum.as_deref())?;
+        }
+
         Ok(())
     }
 
-    /// Fetches the main source file and extracts it, if possible
-    fn prepare_main_source(&mut self, source_url: &str) -> Result<(), BCError> {
-        let url = source_url
-            .replace("$PKG_NAME", &self.pkgbuild.name)
-            .replace("$PKG_VERSION", &self.pkgbuild.version);
+    /// Fetches a source file and extracts it, if possible and wanted
+    /// # Arguments
+    /// * `source` - The expanded source to prepare
+    /// * `checksum` - The expected SHA-256 checksum of the source
+    fn prepare_source(&mut self, source: &Source, checksum: Option<&str>) -> Result<(), BCError> {
+        // Resolve references to the URL to fetch from
+        let url = if source.is_reference() {
+            match &self.resolver {
+                Some(resolver) => resolver
+                    .resolve(&source.location)
+                    .err_prepend(&format!("When resolving source {}", source.location))?,
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!(
+                            "Source {} is a reference, but there is no resolver",
+                            source.location
+                        ),
+                    )
+                    .into())
+                }
+            }
+        } else {
+            source.resolve_relative(self.pkgbuild.origin.as_deref())?
+        };
+
+        if let Some(git_url) = source.git_url() {
+            // Repositories are checked out to a directory named like them, without `.git`
+            let name = match source.file_name(git_url) {
+                Some(name) => name.strip_suffix(".git").unwrap_or(name).to_owned(),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Source {url} has no repository name, use the rename option"),
+                    )
+                    .into())
+                }
+            };
+
+            if checksum.is_some() {
+                warn!(
+                    "Ignoring checksum of git source {}, it is verified by its commit",
+                    url
+                );
+            }
+
+            return self.prepare_git_source(source, git_url, &name);
+        }
 
         // Parse out the file name
-        let name = &url
-            .split("/")
-            .last()
-            .expect("File name of source file")
-            .to_string();
+        let name = match source.file_name(&url) {
+            Some(name) => name.to_owned(),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Source {url} has no file name, use the rename option"),
+                )
+                .into())
+            }
+        };
 
         // Construct a destination path
         let dst_path = self
@@ -44,63 +127,50 @@ impl<'a> BuildContext<'a> {
             dst_path.to_string_lossy()
         );
 
-        // Open the destination file handle
-        let mut source_file = File::options()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(&dst_path)
-            .err_prepend(&format!(
-                "Open main source file handle at {}",
-                dst_path.to_string_lossy()
-            ))?;
-
-        // Download the file using the leaf handler for that
-        leaf::error::LErrorExt::err_prepend(
-            download::download(
-                &url,
-                &format!("Downloading source file {}", &name),
-                true,
-                |data| {
-                    source_file.write(data).expect("When writing out file");
-                    true
-                },
-            ),
-            &format!("When fetching source from {}", url),
-        )?;
-
-        // Seek to begin, read magic bytes and seek to start
-        source_file
-            .seek(std::io::SeekFrom::Start(0))
-            .err_prepend("When seeking to start of source file")?;
+        // Fetch the source, through the cache if it is remote, into the build directory
+        self.fetch_source(&url, checksum, &dst_path)?;
+
+        if source.extract() == Some(false) {
+            info!("Keeping source {} as-is", name);
+            return Ok(());
+        }
+
+        // Open the source, read magic bytes and seek to start
+        let mut source_file = File::open(&dst_path).err_prepend(&format!(
+            "Open source file handle at {}",
+            dst_path.to_string_lossy()
+        ))?;
         let mut buf: [u8; 8] = [0; 8];
-        source_file
-            .read_exact(&mut buf)
+        let len = source_file
+            .read(&mut buf)
             .err_prepend("When reading magic bytes of source file")?;
+        let buf = &buf[..len];
         source_file
             .seek(std::io::SeekFrom::Start(0))
             .err_prepend("When seeking to start of source file")?;
 
         // Automatically extract XZ, GZ, ZIP archives
-        if infer::archive::is_xz(&buf) {
+        if infer::archive::is_xz(buf) {
             info!("Source is a XZ archive, extracting...");
             let tar = XzDecoder::new(source_file);
 
             let mut archive = Archive::new(tar);
             archive.set_overwrite(true);
             archive.unpack(&self.config.get_buildroot_build_dir(&self.pkgbuild))?;
-        } else if infer::archive::is_gz(&buf) {
+        } else if infer::archive::is_gz(buf) {
             info!("Source is a GZ archive, extracting...");
             let tar = GzDecoder::new(source_file);
 
             let mut archive = Archive::new(tar);
             archive.set_overwrite(true);
             archive.unpack(&self.config.get_buildroot_build_dir(&self.pkgbuild))?;
-        } else if infer::archive::is_zip(&buf) {
+        } else if infer::archive::is_zip(buf) {
             info!("Source is a ZIP archive, extracting...");
 
             let mut zip = zip::ZipArchive::new(source_file)?;
             zip.extract(&self.config.get_buildroot_build_dir(&self.pkgbuild))?;
+        } else if source.extract() == Some(true) {
+            warn!("Source {} is not a known archive, keeping it as-is", name);
         }
 
         Ok(())
@@ -0,0 +1,120 @@
+use super::BuildContext;
+use crate::util::glob_match;
+use crate::{BCError, SplitPackage, StdIOErrorExt};
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+impl<'a> BuildContext<'a> {
+    /// Populates the target directory of a split package: Runs its `package` phase and
+    /// moves the files matching its globs out of the install directory of the main package
+    /// # Arguments
+    /// * `split` - The split package to populate
+    pub(super) fn package_split(&mut self, split: &SplitPackage) -> Result<(), BCError> {
+        match &split.package {
+            Some(script) => {
+                info!("PACKAGE script of {} exists, running...", split.name);
+                let status = self.run_script(
+                    script,
+                    &format!("package_{}.sh", split.name),
+                    &format!("/split/{}", split.name),
+                )?;
+                info!(
+                    "PACKAGE script of {} is done: SUCCESS: {}",
+                    split.name,
+                    status.success()
+                )
+            }
+            None => {
+                info!("PACKAGE script of {} does not exist, skipping", split.name);
+            }
+        }
+
+        if let Some(files) = &split.files {
+            let src = self.config.get_target_dir(&self.pkgbuild).join("data");
+            let dst = self
+                .config
+                .get_output_target_dir(&self.pkgbuild, &split.name)
+                .join("data");
+
+            info!("Moving files of {} out of the main package", split.name);
+            move_matching(&src, &dst, files).err_prepend(&format!(
+                "When moving the files of split package {}",
+                split.name
+            ))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Moves all the paths below `src` matching one of the globs to the same place below `dst`.
+///
+/// Matching directories are moved as a whole.
+/// # Arguments
+/// * `src` - The directory to move the paths out of
+/// * `dst` - The directory to move the paths to
+/// * `patterns` - The globs to match the paths relative to `src` against
+fn move_matching(src: &Path, dst: &Path, patterns: &[String]) -> Result<(), Error> {
+    let patterns: Vec<&str> = patterns.iter().map(|p| p.trim_start_matches('/')).collect();
+
+    let mut matched: Vec<PathBuf> = Vec::new();
+    if src.exists() {
+        collect_matching(src, Path::new(""), &patterns, &mut matched)?;
+    }
+
+    for pattern in &patterns {
+        if !matched
+            .iter()
+            .any(|path| glob_match(pattern, &path.to_string_lossy()))
+        {
+            warn!("The glob '{pattern}' did not match any files");
+        }
+    }
+
+    for path in matched {
+        let target = dst.join(&path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        debug!("Moving {}", path.to_string_lossy());
+        fs::rename(src.join(&path), &target).err_prepend(&format!(
+            "When moving {} to {}",
+            path.to_string_lossy(),
+            target.to_string_lossy()
+        ))?;
+    }
+
+    Ok(())
+}
+
+/// Collects the paths below a directory matching one of the globs, without
+/// descending into matching directories or following symlinks
+/// # Arguments
+/// * `root` - The directory the paths are relative to
+/// * `dir` - The directory to search, relative to `root`
+/// * `patterns` - The globs to match against
+/// * `matched` - The list to collect the matching relative paths into
+fn collect_matching(
+    root: &Path,
+    dir: &Path,
+    patterns: &[&str],
+    matched: &mut Vec<PathBuf>,
+) -> Result<(), Error> {
+    for entry in fs::read_dir(root.join(dir))? {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+
+        if patterns
+            .iter()
+            .any(|pattern| glob_match(pattern, &path.to_string_lossy()))
+        {
+            matched.push(path);
+        } else if entry.file_type()?.is_dir() {
+            collect_matching(root, &path, patterns, matched)?;
+        }
+    }
+
+    Ok(())
+}
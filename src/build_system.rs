@@ -0,0 +1,300 @@
+use crate::{Dependency, PackageBuild};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    io::{Error, ErrorKind},
+    str::FromStr,
+};
+
+/// The directory the presets change into before running: `$SRC_DIR`, which packagebuilds
+/// can declare in their `vars` block, or `$PKG_NAME-$PKG_VERSION` if it is not set
+const CD_SOURCE: &str = "cd \"${SRC_DIR:-$PKG_NAME-$PKG_VERSION}\"";
+
+/// A build system with standard phase scripts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildSystem {
+    CMake,
+    Meson,
+    Autotools,
+    Make,
+    Cargo,
+    Python,
+}
+
+/// The phase scripts and build dependencies a build system supplies.
+///
+/// Every script changes into `$SRC_DIR`, or `$PKG_NAME-$PKG_VERSION` if that is not set.
+/// `SRC_DIR` is not set by the builder, packagebuilds declare it in their `vars` block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildSystemPreset {
+    /// The build dependencies needed by the build system
+    pub build_dependencies: Vec<Dependency>,
+    /// The `build` phase script
+    pub build: Option<Vec<String>>,
+    /// The `check` phase script
+    pub check: Option<Vec<String>>,
+    /// The `package` phase script
+    pub package: Option<Vec<String>>,
+}
+
+impl BuildSystem {
+    /// Returns the preset of phase scripts and build dependencies for this build system
+    pub fn preset(&self) -> BuildSystemPreset {
+        let (deps, build, check, package): (&[&str], &[&str], &[&str], &[&str]) = match self {
+            Self::CMake => (
+                &["cmake", "ninja"],
+                &[
+                    "cmake -B build -G Ninja -DCMAKE_INSTALL_PREFIX=/usr -DCMAKE_BUILD_TYPE=Release",
+                    "cmake --build build",
+                ],
+                &["ctest --test-dir build --output-on-failure"],
+                &["DESTDIR=\"$PKG_INSTALL_DIR\" cmake --install build"],
+            ),
+            Self::Meson => (
+                &["meson", "ninja"],
+                &[
+                    "meson setup build --prefix=/usr --buildtype=release",
+                    "meson compile -C build",
+                ],
+                &["meson test -C build"],
+                &["meson install -C build --destdir \"$PKG_INSTALL_DIR\""],
+            ),
+            Self::Autotools => (
+                &["make"],
+                &["./configure --prefix=/usr", "make"],
+                &["make check"],
+                &["make DESTDIR=\"$PKG_INSTALL_DIR\" install"],
+            ),
+            Self::Make => (
+                &["make"],
+                &["make PREFIX=/usr"],
+                &["make check"],
+                &["make PREFIX=/usr DESTDIR=\"$PKG_INSTALL_DIR\" install"],
+            ),
+            Self::Cargo => (
+                &["rust"],
+                &["cargo build --release --locked"],
+                &["cargo test --release --locked"],
+                &["cargo install --path . --root \"$PKG_INSTALL_DIR/usr\" --locked --no-track"],
+            ),
+            Self::Python => (
+                &["python", "python-build", "python-installer"],
+                &["python3 -m build --wheel --no-isolation"],
+                &[],
+                &["python3 -m installer --destdir=\"$PKG_INSTALL_DIR\" dist/*.whl"],
+            ),
+        };
+
+        let script = |lines: &[&str]| -> Option<Vec<String>> {
+            if lines.is_empty() {
+                return None;
+            }
+
+            let mut script = vec![CD_SOURCE.to_owned()];
+            script.extend(lines.iter().map(|l| l.to_string()));
+            Some(script)
+        };
+
+        BuildSystemPreset {
+            build_dependencies: deps.iter().map(|d| Dependency::new(d)).collect(),
+            build: script(build),
+            check: script(check),
+            package: script(package),
+        }
+    }
+}
+
+impl PackageBuild {
+    /// Returns a copy of this packagebuild with the preset of its build system applied.
+    ///
+    /// The `build`, `check` and `package` phases the packagebuild omits are taken from the
+    /// preset and the build dependencies of the preset are added if they are missing.
+    /// Explicit phases of the packagebuild always win.
+    pub fn apply_build_system(&self) -> PackageBuild {
+        let preset = match &self.build_system {
+            Some(build_system) => build_system.preset(),
+            None => return self.clone(),
+        };
+
+        let mut res = self.clone();
+        let mut used = false;
+
+        for (phase, preset_phase) in [
+            (&mut res.build, preset.build),
+            (&mut res.check, preset.check),
+            (&mut res.package, preset.package),
+        ] {
+            if phase.is_none() && preset_phase.is_some() {
+                *phase = preset_phase;
+                used = true;
+            }
+        }
+
+        if used {
+            let deps = res.build_dependencies.get_or_insert_with(Vec::new);
+            for dep in preset.build_dependencies {
+                if !deps.iter().any(|d| d.name == dep.name) {
+                    deps.push(dep);
+                }
+            }
+        }
+
+        res
+    }
+}
+
+impl Display for BuildSystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CMake => write!(f, "cmake"),
+            Self::Meson => write!(f, "meson"),
+            Self::Autotools => write!(f, "autotools"),
+            Self::Make => write!(f, "make"),
+            Self::Cargo => write!(f, "cargo"),
+            Self::Python => write!(f, "python"),
+        }
+    }
+}
+
+impl FromStr for BuildSystem {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cmake" => Ok(Self::CMake),
+            "meson" => Ok(Self::Meson),
+            "autotools" => Ok(Self::Autotools),
+            "make" => Ok(Self::Make),
+            "cargo" => Ok(Self::Cargo),
+            "python" => Ok(Self::Python),
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unknown build system '{s}'"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: &[BuildSystem] = &[
+        BuildSystem::CMake,
+        BuildSystem::Meson,
+        BuildSystem::Autotools,
+        BuildSystem::Make,
+        BuildSystem::Cargo,
+        BuildSystem::Python,
+    ];
+
+    fn lines(lines: &[&str]) -> Option<Vec<String>> {
+        Some(lines.iter().map(|l| l.to_string()).collect())
+    }
+
+    #[test]
+    fn presets_fill_missing_phases() {
+        for build_system in ALL {
+            let mut pkgbuild = PackageBuild::new("foo", "1.0", 0);
+            pkgbuild.build_system = Some(*build_system);
+            pkgbuild.prepare = lines(&["./autogen.sh"]);
+
+            let preset = build_system.preset();
+            let applied = pkgbuild.apply_build_system();
+            assert_eq!(applied.prepare, pkgbuild.prepare);
+            assert_eq!(applied.build, preset.build);
+            assert_eq!(applied.check, preset.check);
+            assert_eq!(applied.package, preset.package);
+            assert_eq!(
+                applied.build_dependencies.unwrap(),
+                preset.build_dependencies
+            );
+
+            for script in [preset.build, preset.check, preset.package]
+                .iter()
+                .flatten()
+            {
+                assert_eq!(script[0], CD_SOURCE, "{build_system}");
+            }
+        }
+    }
+
+    #[test]
+    fn explicit_phases_win() {
+        let mut pkgbuild = PackageBuild::new("foo", "1.0", 0);
+        pkgbuild.build_system = Some(BuildSystem::CMake);
+        pkgbuild.build = lines(&["cmake -B build -DFOO=1", "cmake --build build"]);
+        pkgbuild.check = Some(Vec::new());
+        pkgbuild.build_dependencies = Some(vec!["cmake>=3.20".parse().unwrap()]);
+
+        let applied = pkgbuild.apply_build_system();
+        assert_eq!(applied.build, pkgbuild.build);
+        assert_eq!(applied.check, Some(Vec::new()));
+        assert_eq!(applied.package, BuildSystem::CMake.preset().package);
+
+        let deps: Vec<String> = applied
+            .build_dependencies
+            .unwrap()
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(deps, vec!["cmake>=3.20", "ninja"]);
+    }
+
+    #[test]
+    fn unused_presets_add_nothing() {
+        let mut pkgbuild = PackageBuild::new("foo", "1.0", 0);
+        pkgbuild.build_system = Some(BuildSystem::Python);
+        pkgbuild.build = lines(&["make"]);
+        pkgbuild.package = lines(&["make install"]);
+
+        // Python has no check preset, so no phase is taken from it
+        assert_eq!(pkgbuild.apply_build_system(), pkgbuild);
+        assert_eq!(
+            PackageBuild::new("foo", "1.0", 0).apply_build_system(),
+            PackageBuild::new("foo", "1.0", 0)
+        );
+    }
+
+    #[test]
+    fn source_dir_fallback() {
+        let dir = std::env::temp_dir().join(format!("pkgbuild-src-dir-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("foo-1.0")).unwrap();
+        std::fs::create_dir_all(dir.join("foo-src")).unwrap();
+
+        let cd = |src_dir: Option<&str>| {
+            let mut command = std::process::Command::new("sh");
+            command
+                .arg("-c")
+                .arg(format!("{CD_SOURCE} && pwd"))
+                .current_dir(&dir)
+                .env("PKG_NAME", "foo")
+                .env("PKG_VERSION", "1.0")
+                .env_remove("SRC_DIR");
+            if let Some(src_dir) = src_dir {
+                command.env("SRC_DIR", src_dir);
+            }
+            let output = command.output().unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        let default = cd(None);
+        let declared = cd(Some("foo-src"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(default.trim_end().ends_with("/foo-1.0"), "{default}");
+        assert!(declared.trim_end().ends_with("/foo-src"), "{declared}");
+    }
+
+    #[test]
+    fn names() {
+        for build_system in ALL {
+            assert_eq!(
+                build_system.to_string().parse::<BuildSystem>().unwrap(),
+                *build_system
+            );
+        }
+        assert!("scons".parse::<BuildSystem>().is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{expand_str, BCError, FetcherRegistry, PackageBuild, Source, StdIOErrorExt};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Display,
+    io::{Error, Read},
+};
+
+/// The checksum of an extra source that does not get verified
+pub const CHECKSUM_SKIP: &str = "SKIP";
+
+/// The checksum of a source did not match the expected one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    /// The source that failed verification
+    pub source: String,
+    /// The expected SHA-256 checksum
+    pub expected: String,
+    /// The actual SHA-256 checksum
+    pub actual: String,
+}
+
+/// Splits the `sha256` option off the supplied source: `<url>#sha256=<checksum>`
+/// # Arguments
+/// * `source` - The source to split
+pub fn split_inline_checksum(source: &str) -> Result<(String, Option<String>), Error> {
+    let mut source: Source = source.parse()?;
+    let checksum = source.take_option("sha256");
+
+    Ok((source.to_string(), checksum))
+}
+
+/// Checks if the supplied string is a SHA-256 checksum: 64 hexadecimal digits
+/// # Arguments
+/// * `checksum` - The string to check
+pub fn is_sha256(checksum: &str) -> bool {
+    checksum.len() == 64 && checksum.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Returns the hexadecimal representation of a SHA-256 hasher's digest
+/// # Arguments
+/// * `hasher` - The hasher to finalize
+fn hex_digest(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Computes the SHA-256 checksum of the supplied input in hexadecimal form
+/// # Arguments
+/// * `input` - The input to read to its end
+pub fn sha256<R: Read>(input: &mut R) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 8192];
+
+    loop {
+        let len = input.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+    }
+
+    Ok(hex_digest(hasher))
+}
+
+/// Verifies that the supplied input has the expected SHA-256 checksum
+/// # Arguments
+/// * `source` - The name of the source, used for the error
+/// * `input` - The input to read to its end
+/// * `expected` - The expected checksum
+pub fn verify_sha256<R: Read>(source: &str, input: &mut R, expected: &str) -> Result<(), BCError> {
+    let actual = sha256(input).err_prepend(&format!("When computing checksum of {source}"))?;
+
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(ChecksumMismatch {
+            source: source.to_owned(),
+            expected: expected.to_owned(),
+            actual,
+        }
+        .into())
+    }
+}
+
+/// Fetches the supplied URL and computes its SHA-256 checksum
+/// # Arguments
+/// * `url` - The URL to fetch
+/// * `fetchers` - The fetchers to fetch the URL with
+pub fn sha256_url(url: &str, fetchers: &FetcherRegistry) -> Result<String, BCError> {
+    let mut hasher = Sha256::new();
+
+    info!("Checksumming {}", url);
+    fetchers.fetch(url, &mut hasher)?;
+
+    Ok(hex_digest(hasher))
+}
+
+impl PackageBuild {
+    /// Returns a copy of this packagebuild with the checksums of all its sources
+    /// computed by downloading them, so maintainers can update them.
+    ///
+    /// Extra sources that are references or git repositories get the `SKIP` checksum,
+    /// git repositories are verified by their commit instead.
+    /// Inline checksums are replaced by the computed ones.
+    /// # Arguments
+    /// * `fetchers` - The fetchers to fetch the sources with
+    pub fn compute_checksums(&self, fetchers: &FetcherRegistry) -> Result<PackageBuild, BCError> {
+        let variables = self.resolved_variables()?;
+        let checksum = |source: &str| -> Result<Option<String>, BCError> {
+            let source: Source = expand_str(source, &variables)?.parse()?;
+            if source.is_reference() || source.is_git() {
+                return Ok(None);
+            }
+            let url = source.resolve_relative(self.origin.as_deref())?;
+            Ok(Some(sha256_url(&url, fetchers)?))
+        };
+
+        let mut res = self.clone();
+
+        if let Some(source) = &self.source {
+            res.source_sha256 = checksum(source)?;
+        }
+
+        if let Some(sources) = &self.extra_sources {
+            let mut checksums: Vec<String> = Vec::new();
+            for source in sources {
+                checksums.push(checksum(source)?.unwrap_or_else(|| CHECKSUM_SKIP.to_owned()));
+            }
+            res.extra_sources_sha256 = Some(checksums);
+        }
+
+        for arch_override in res.arch_overrides.iter_mut().flatten() {
+            if let Some(source) = &arch_override.source {
+                arch_override.source_sha256 = checksum(source)?;
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+impl Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Checksum mismatch for {}: expected sha256 {}, got {}",
+            self.source, self.expected, self.actual
+        )
+    }
+}
@@ -9,6 +9,30 @@ pub struct BuilderConfiguration {
     pub root: PathBuf,
     /// The environment to use
     pub environment: BuildEnvironment,
+    /// The architecture to build for, defaults to the one of the builder
+    #[serde(default = "default_arch")]
+    pub arch: String,
+    /// Never access the network: Sources only come from the source cache
+    /// and the package lists of leaf are not updated
+    #[serde(default)]
+    pub offline: bool,
+    /// The cross-compilation setup, builds natively if not set
+    #[serde(default)]
+    pub cross: Option<CrossConfiguration>,
+}
+
+/// The triplets of a cross build
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CrossConfiguration {
+    /// The triplet of the machine building the package, such as `x86_64-linux-gnu`
+    pub host: String,
+    /// The triplet of the machine the package is built for, such as `aarch64-linux-gnu`
+    pub target: String,
+}
+
+/// Returns the architecture of the builder, the default target architecture
+fn default_arch() -> String {
+    std::env::consts::ARCH.to_owned()
 }
 
 /// An environment consisting of a name and available / required packages
@@ -41,6 +65,11 @@ impl BuilderConfiguration {
         self.root.join("target")
     }
 
+    /// The path to the packagebuild templates: `<root>/templates`
+    pub fn get_templates_dir(&self) -> PathBuf {
+        self.root.join("templates")
+    }
+
     /// Get the path to the current environment root
     pub fn get_environment_root_dir(&self) -> PathBuf {
         self.get_environments_dir().join(&self.environment.name)
@@ -51,6 +80,16 @@ impl BuilderConfiguration {
         self.get_cache_dir().join("leaf")
     }
 
+    /// The content-addressed cache of downloaded sources: `<root>/cache/sources`
+    pub fn get_source_cache_dir(&self) -> PathBuf {
+        self.get_cache_dir().join("sources")
+    }
+
+    /// The bare clones of git repository sources: `<root>/cache/git`
+    pub fn get_git_cache_dir(&self) -> PathBuf {
+        self.get_cache_dir().join("git")
+    }
+
     /// The path for the overlayfs `work` dir
     pub fn get_overlay_work_dir(&self) -> PathBuf {
         self.get_cache_dir().join("overlay_work")
@@ -71,9 +110,18 @@ impl BuilderConfiguration {
 
     /// The directory to store the build target (artifact) in
     pub fn get_target_dir(&self, pkgbuild: &PackageBuild) -> PathBuf {
+        self.get_output_target_dir(pkgbuild, &pkgbuild.name)
+    }
+
+    /// The directory to store the build target (artifact) of one of the packages
+    /// a packagebuild produces in: `<name>-<version>-<real_version>/package`
+    /// # Arguments
+    /// * `pkgbuild` - The packagebuild producing the package
+    /// * `name` - The name of the package, the main or a split package
+    pub fn get_output_target_dir(&self, pkgbuild: &PackageBuild, name: &str) -> PathBuf {
         self.get_targets_dir().join(format!(
             "{}-{}-{}/package",
-            pkgbuild.name, pkgbuild.version, pkgbuild.real_version
+            name, pkgbuild.version, pkgbuild.real_version
         ))
     }
 
@@ -82,12 +130,43 @@ impl BuilderConfiguration {
         self.get_build_dir(pkgbuild).join("target")
     }
 
+    /// The target directory of a split package within the build root: `split/<name>`
+    /// # Arguments
+    /// * `pkgbuild` - The packagebuild producing the split package
+    /// * `name` - The name of the split package
+    pub fn get_buildroot_split_target_dir(&self, pkgbuild: &PackageBuild, name: &str) -> PathBuf {
+        self.get_build_dir(pkgbuild).join("split").join(name)
+    }
+
+    /// The sysroot of the target machine within the build root for cross builds
+    pub fn get_buildroot_sysroot_dir(&self, pkgbuild: &PackageBuild) -> PathBuf {
+        self.get_build_dir(pkgbuild).join("sysroot")
+    }
+
     /// The `build` directory location within the build root
     pub fn get_buildroot_build_dir(&self, pkgbuild: &PackageBuild) -> PathBuf {
         self.get_build_dir(pkgbuild).join("build")
     }
 }
 
+impl CrossConfiguration {
+    /// Creates a new cross-compilation setup
+    /// # Arguments
+    /// * `host` - The triplet of the machine building the package
+    /// * `target` - The triplet of the machine the package is built for
+    pub fn new(host: &str, target: &str) -> Self {
+        Self {
+            host: host.to_owned(),
+            target: target.to_owned(),
+        }
+    }
+
+    /// The architecture of the target machine, the first component of its triplet
+    pub fn target_arch(&self) -> &str {
+        self.target.split('-').next().unwrap_or_default()
+    }
+}
+
 impl BuildEnvironment {
     /// Create a new build environment from scratch
     /// # Arguments
@@ -104,3 +183,70 @@ impl BuildEnvironment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder_config(cross: Option<CrossConfiguration>) -> BuilderConfiguration {
+        BuilderConfiguration {
+            root: PathBuf::from("/var/lib/pkgbuild"),
+            environment: BuildEnvironment::new("base", vec!["glibc"]),
+            arch: "aarch64".to_owned(),
+            offline: false,
+            cross,
+        }
+    }
+
+    #[test]
+    fn target_arch() {
+        let cross = CrossConfiguration::new("x86_64-linux-gnu", "aarch64-linux-gnu");
+        assert_eq!(cross.host, "x86_64-linux-gnu");
+        assert_eq!(cross.target_arch(), "aarch64");
+        assert_eq!(
+            CrossConfiguration::new("x86_64", "riscv64").target_arch(),
+            "riscv64"
+        );
+        assert_eq!(CrossConfiguration::new("x86_64", "").target_arch(), "");
+    }
+
+    #[test]
+    fn sysroot_dir() {
+        let config = builder_config(Some(CrossConfiguration::new(
+            "x86_64-linux-gnu",
+            "aarch64-linux-gnu",
+        )));
+        let pkgbuild = PackageBuild::new("foo", "1.0", 2);
+
+        assert_eq!(
+            config.get_buildroot_sysroot_dir(&pkgbuild),
+            PathBuf::from("/var/lib/pkgbuild/build/foo-1.0-2/sysroot")
+        );
+        assert!(config
+            .get_buildroot_sysroot_dir(&pkgbuild)
+            .starts_with(config.get_build_dir(&pkgbuild)));
+    }
+
+    #[test]
+    fn deserialize_cross() {
+        let config: BuilderConfiguration = serde_json::from_str(
+            r#"{
+                "root": "/var/lib/pkgbuild",
+                "environment": { "name": "base", "packages": [] },
+                "arch": "aarch64",
+                "cross": { "host": "x86_64-linux-gnu", "target": "aarch64-linux-gnu" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.cross.unwrap().target_arch(), "aarch64");
+        assert!(!config.offline);
+
+        let native: BuilderConfiguration = serde_json::from_str(
+            r#"{ "root": "/", "environment": { "name": "base", "packages": [] } }"#,
+        )
+        .unwrap();
+        assert!(native.cross.is_none());
+        assert_eq!(native.arch, std::env::consts::ARCH);
+        assert!(builder_config(None).cross.is_none());
+    }
+}
@@ -0,0 +1,333 @@
+use crate::compare_versions;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    io::{Error, ErrorKind},
+    str::FromStr,
+};
+
+/// The comparison operator of a version constraint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstraintOp {
+    /// `=`
+    Eq,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+}
+
+/// A version constraint on a dependency, such as `>=3.20`.
+///
+/// A version ending in `*` matches all versions starting with the part before it (`=3.11*`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    /// The comparison operator
+    pub op: ConstraintOp,
+    /// The version to compare against
+    pub version: String,
+}
+
+/// A dependency on a package in the form `name[<op><version>][: reason]`.
+///
+/// Examples: `cmake>=3.20`, `openssl<4`, `python=3.11*` or `sqlite: For the database backend`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    /// The name of the package depended on
+    pub name: String,
+    /// The optional version constraint
+    pub constraint: Option<Constraint>,
+    /// The optional reason for the dependency, used for optional dependencies
+    pub reason: Option<String>,
+}
+
+impl Dependency {
+    /// Creates a new dependency without a constraint or reason
+    /// # Arguments
+    /// * `name` - The name of the package depended on
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            constraint: None,
+            reason: None,
+        }
+    }
+
+    /// Returns the dependency without its reason in the form the installer expects it:
+    /// `name[<op><version>]`
+    pub fn to_install_spec(&self) -> String {
+        match &self.constraint {
+            Some(constraint) => format!("{}{}", self.name, constraint),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Checks if the supplied version of the package satisfies this dependency
+    /// # Arguments
+    /// * `version` - The version to check
+    pub fn matches(&self, version: &str) -> bool {
+        match &self.constraint {
+            Some(constraint) => constraint.matches(version),
+            None => true,
+        }
+    }
+}
+
+impl Constraint {
+    /// Checks if the supplied version satisfies this constraint.
+    ///
+    /// A `*` wildcard only matches whole components: `=3.1*` matches `3.1` and `3.1.2`, not `3.11`
+    /// # Arguments
+    /// * `version` - The version to check
+    pub fn matches(&self, version: &str) -> bool {
+        if let Some(prefix) = self.version.strip_suffix('*') {
+            let is_separator = |c: char| matches!(c, '.' | '-' | '+' | '~');
+            let prefixed = version.strip_prefix(prefix).is_some_and(|rest| {
+                rest.is_empty() || prefix.ends_with(is_separator) || rest.starts_with(is_separator)
+            });
+            return match self.op {
+                ConstraintOp::Eq => prefixed,
+                op => op.matches(compare_versions(version, prefix)),
+            };
+        }
+
+        self.op.matches(compare_versions(version, &self.version))
+    }
+}
+
+impl ConstraintOp {
+    /// Checks if the result of comparing a version to the constraint satisfies this operator
+    /// # Arguments
+    /// * `ordering` - The ordering of the version compared to the constraint version
+    pub fn matches(&self, ordering: Ordering) -> bool {
+        match self {
+            Self::Eq => ordering == Ordering::Equal,
+            Self::Lt => ordering == Ordering::Less,
+            Self::Le => ordering != Ordering::Greater,
+            Self::Gt => ordering == Ordering::Greater,
+            Self::Ge => ordering != Ordering::Less,
+        }
+    }
+
+    /// Returns the textual representation of the operator
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Eq => "=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+}
+
+impl Display for ConstraintOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Display for Constraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.op, self.version)
+    }
+}
+
+impl Display for Dependency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_install_spec())?;
+        if let Some(reason) = &self.reason {
+            write!(f, ": {reason}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Dependency {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+
+        let (spec, reason) = match s.split_once(": ") {
+            Some((spec, reason)) => (spec.trim(), Some(reason.trim().to_owned())),
+            None => (s.trim(), None),
+        };
+
+        let (name, constraint) = match spec.find(['<', '>', '=']) {
+            Some(pos) => {
+                let (name, rest) = spec.split_at(pos);
+                let (op, version) = if let Some(v) = rest.strip_prefix(">=") {
+                    (ConstraintOp::Ge, v)
+                } else if let Some(v) = rest.strip_prefix("<=") {
+                    (ConstraintOp::Le, v)
+                } else if let Some(v) = rest.strip_prefix('>') {
+                    (ConstraintOp::Gt, v)
+                } else if let Some(v) = rest.strip_prefix('<') {
+                    (ConstraintOp::Lt, v)
+                } else {
+                    (ConstraintOp::Eq, &rest[1..])
+                };
+
+                let version = version.trim();
+                if version.is_empty() {
+                    return Err(invalid(format!("Missing version in dependency '{s}'")));
+                }
+
+                (
+                    name.trim(),
+                    Some(Constraint {
+                        op,
+                        version: version.to_owned(),
+                    }),
+                )
+            }
+            None => (spec, None),
+        };
+
+        if name.is_empty() {
+            return Err(invalid(format!("Missing name in dependency '{s}'")));
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            constraint,
+            reason: reason.filter(|r| !r.is_empty()),
+        })
+    }
+}
+
+impl Serialize for Dependency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Dependency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constraint(op: ConstraintOp, version: &str) -> Option<Constraint> {
+        Some(Constraint {
+            op,
+            version: version.to_owned(),
+        })
+    }
+
+    #[test]
+    fn parse_dependencies() {
+        let dependency: Dependency = "cmake>=3.20".parse().unwrap();
+        assert_eq!(dependency.name, "cmake");
+        assert_eq!(dependency.constraint, constraint(ConstraintOp::Ge, "3.20"));
+        assert_eq!(dependency.reason, None);
+
+        let dependency: Dependency = "openssl < 4".parse().unwrap();
+        assert_eq!(dependency.name, "openssl");
+        assert_eq!(dependency.constraint, constraint(ConstraintOp::Lt, "4"));
+
+        let dependency: Dependency = "python=3.11*".parse().unwrap();
+        assert_eq!(dependency.constraint, constraint(ConstraintOp::Eq, "3.11*"));
+
+        let dependency: Dependency = "gcc".parse().unwrap();
+        assert_eq!(dependency, Dependency::new("gcc"));
+
+        for (spec, op) in [
+            ("a<=1", ConstraintOp::Le),
+            ("a>1", ConstraintOp::Gt),
+            ("a=1", ConstraintOp::Eq),
+        ] {
+            assert_eq!(
+                spec.parse::<Dependency>().unwrap().constraint.unwrap().op,
+                op
+            );
+        }
+    }
+
+    #[test]
+    fn parse_reasons() {
+        let dependency: Dependency = "sqlite>=3: For the database backend".parse().unwrap();
+        assert_eq!(dependency.name, "sqlite");
+        assert_eq!(dependency.constraint, constraint(ConstraintOp::Ge, "3"));
+        assert_eq!(
+            dependency.reason.as_deref(),
+            Some("For the database backend")
+        );
+        assert_eq!(dependency.to_install_spec(), "sqlite>=3");
+
+        let dependency: Dependency = "sqlite: ".parse().unwrap();
+        assert_eq!(dependency.reason, None);
+    }
+
+    #[test]
+    fn reject_invalid_dependencies() {
+        assert!("".parse::<Dependency>().is_err());
+        assert!(">=3".parse::<Dependency>().is_err());
+        assert!("cmake>=".parse::<Dependency>().is_err());
+        assert!("cmake<: reason".parse::<Dependency>().is_err());
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for spec in [
+            "cmake>=3.20",
+            "openssl<4",
+            "python=3.11*",
+            "gcc",
+            "sqlite>=3: For the database backend",
+        ] {
+            let dependency: Dependency = spec.parse().unwrap();
+            assert_eq!(dependency.to_string(), spec);
+            assert_eq!(
+                dependency.to_string().parse::<Dependency>().unwrap(),
+                dependency
+            );
+        }
+    }
+
+    #[test]
+    fn json_as_strings() {
+        let dependencies: Vec<Dependency> = vec![
+            "cmake>=3.20".parse().unwrap(),
+            "sqlite: Optional".parse().unwrap(),
+        ];
+
+        let json = serde_json::to_string(&dependencies).unwrap();
+        assert_eq!(json, r#"["cmake>=3.20","sqlite: Optional"]"#);
+        assert_eq!(
+            serde_json::from_str::<Vec<Dependency>>(&json).unwrap(),
+            dependencies
+        );
+        assert!(serde_json::from_str::<Dependency>(r#"">=3""#).is_err());
+    }
+
+    #[test]
+    fn match_versions() {
+        let matches =
+            |spec: &str, version: &str| spec.parse::<Dependency>().unwrap().matches(version);
+
+        assert!(matches("cmake>=3.20", "3.20"));
+        assert!(matches("cmake>=3.20", "3.27.1"));
+        assert!(!matches("cmake>=3.20", "3.9"));
+        assert!(matches("openssl<4", "3.1"));
+        assert!(!matches("openssl<4", "4.0"));
+        assert!(matches("python=3.11*", "3.11.4"));
+        assert!(!matches("python=3.11*", "3.12"));
+        assert!(!matches("python=3.1*", "3.11"));
+        assert!(matches("python=3.1*", "3.1"));
+        assert!(matches("python=3.1*", "3.1~rc1"));
+        assert!(matches("python=3.*", "3.11"));
+        assert!(matches("gcc", "13"));
+    }
+}
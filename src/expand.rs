@@ -0,0 +1,397 @@
+use crate::{ArchOverride, Dependency, PackageBuild, SplitPackage};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+
+/// A user-defined variable in the form `NAME=value`, declared in the `vars` block,
+/// one per line or separated by `;`.
+///
+/// The value may reference the derived variables and previously declared variables,
+/// it cannot contain `;`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    /// The name of the variable
+    pub name: String,
+    /// The unexpanded value of the variable
+    pub value: String,
+}
+
+impl PackageBuild {
+    /// Returns the variables derived from this packagebuild:
+    /// * `PKG_NAME` - The name of the package
+    /// * `PKG_VERSION` - The version of the package
+    /// * `PKG_REAL_VERSION` - The real version of the package
+    /// * `PKG_VERSION_MAJOR`, `PKG_VERSION_MINOR`, `PKG_VERSION_PATCH` - The first three
+    ///   dot-separated components of the version, if present
+    pub fn variables(&self) -> HashMap<String, String> {
+        let mut variables: HashMap<String, String> = HashMap::new();
+
+        variables.insert("PKG_NAME".to_owned(), self.name.clone());
+        variables.insert("PKG_VERSION".to_owned(), self.version.clone());
+        variables.insert("PKG_REAL_VERSION".to_owned(), self.real_version.to_string());
+
+        let components = [
+            "PKG_VERSION_MAJOR",
+            "PKG_VERSION_MINOR",
+            "PKG_VERSION_PATCH",
+        ];
+        for (name, value) in components.iter().zip(self.version.split('.')) {
+            variables.insert(name.to_string(), value.to_owned());
+        }
+
+        variables
+    }
+
+    /// Returns the derived variables together with the expanded variables
+    /// declared in the `vars` block
+    pub fn resolved_variables(&self) -> Result<HashMap<String, String>, Error> {
+        let mut variables = self.variables();
+
+        for variable in self.vars.iter().flatten() {
+            if variables.contains_key(&variable.name) {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("Variable '{}' is already defined", variable.name),
+                ));
+            }
+
+            let value = expand_str(&variable.value, &variables)?;
+            variables.insert(variable.name.clone(), value);
+        }
+
+        Ok(variables)
+    }
+
+    /// Returns a copy of this packagebuild with all variable references in its string fields
+    /// expanded, the scripts are left to the shell.
+    ///
+    /// Of dependencies, only the name and constraint are expanded, not the reason.
+    ///
+    /// The variables of the packagebuild (see `resolved_variables()`) take precedence
+    /// over the supplied ones.
+    /// # Arguments
+    /// * `variables` - Additional, user-defined variables
+    pub fn expand(&self, variables: &HashMap<String, String>) -> Result<PackageBuild, Error> {
+        let mut all = variables.clone();
+        all.extend(self.resolved_variables()?);
+
+        let string = |value: &Option<String>| -> Result<Option<String>, Error> {
+            value.as_ref().map(|v| expand_str(v, &all)).transpose()
+        };
+        let vec = |value: &Option<Vec<String>>| -> Result<Option<Vec<String>>, Error> {
+            value
+                .as_ref()
+                .map(|v| v.iter().map(|s| expand_str(s, &all)).collect())
+                .transpose()
+        };
+        let deps = |value: &Option<Vec<Dependency>>| -> Result<Option<Vec<Dependency>>, Error> {
+            value
+                .as_ref()
+                .map(|v| {
+                    // The reasons are free text that may well contain a `$`
+                    v.iter()
+                        .map(|d| {
+                            let expanded: Dependency =
+                                expand_str(&d.to_install_spec(), &all)?.parse()?;
+                            Ok(Dependency {
+                                reason: d.reason.clone(),
+                                ..expanded
+                            })
+                        })
+                        .collect()
+                })
+                .transpose()
+        };
+
+        let split_packages = self
+            .split_packages
+            .as_ref()
+            .map(|splits| {
+                splits
+                    .iter()
+                    .map(|split| {
+                        Ok(SplitPackage {
+                            description: string(&split.description)?,
+                            provides: vec(&split.provides)?,
+                            extra_dependencies: deps(&split.extra_dependencies)?,
+                            optional_dependencies: deps(&split.optional_dependencies)?,
+                            files: vec(&split.files)?,
+                            ..split.clone()
+                        })
+                    })
+                    .collect::<Result<Vec<SplitPackage>, Error>>()
+            })
+            .transpose()?;
+
+        let arch_overrides = self
+            .arch_overrides
+            .as_ref()
+            .map(|overrides| {
+                overrides
+                    .iter()
+                    .map(|o| {
+                        Ok(ArchOverride {
+                            source: string(&o.source)?,
+                            extra_dependencies: deps(&o.extra_dependencies)?,
+                            optional_dependencies: deps(&o.optional_dependencies)?,
+                            build_dependencies: deps(&o.build_dependencies)?,
+                            cross_dependencies: deps(&o.cross_dependencies)?,
+                            ..o.clone()
+                        })
+                    })
+                    .collect::<Result<Vec<ArchOverride>, Error>>()
+            })
+            .transpose()?;
+
+        Ok(PackageBuild {
+            maintainer: string(&self.maintainer)?,
+            maintainer_email: string(&self.maintainer_email)?,
+            description: string(&self.description)?,
+            provides: vec(&self.provides)?,
+            source: string(&self.source)?,
+            extra_sources: vec(&self.extra_sources)?,
+            extra_dependencies: deps(&self.extra_dependencies)?,
+            optional_dependencies: deps(&self.optional_dependencies)?,
+            build_dependencies: deps(&self.build_dependencies)?,
+            cross_dependencies: deps(&self.cross_dependencies)?,
+            preinstall: string(&self.preinstall)?,
+            postinstall: string(&self.postinstall)?,
+            split_packages,
+            arch_overrides,
+            ..self.clone()
+        })
+    }
+}
+
+/// Expands all `$VAR` and `${VAR}` references in the supplied string.
+///
+/// `$$` produces a literal `$`, a `$` not followed by a variable name is kept as-is.
+/// References to undefined variables are an error.
+/// # Arguments
+/// * `input` - The string to expand
+/// * `variables` - The variables to use
+pub fn expand_str(input: &str, variables: &HashMap<String, String>) -> Result<String, Error> {
+    let mut res = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+
+    let is_name_char = |c: &char| c.is_ascii_alphanumeric() || *c == '_';
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            res.push(c);
+            continue;
+        }
+
+        let name: String = match chars.peek() {
+            Some('$') => {
+                chars.next();
+                res.push('$');
+                continue;
+            }
+            Some('{') => {
+                chars.next();
+                let mut name = String::new();
+                let mut terminated = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        terminated = true;
+                        break;
+                    }
+                    name.push(c);
+                }
+
+                if !terminated {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Unterminated variable reference in '{input}'"),
+                    ));
+                }
+                name
+            }
+            Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(is_name_char) {
+                    name.push(c);
+                }
+                name
+            }
+            _ => {
+                res.push('$');
+                continue;
+            }
+        };
+
+        match variables.get(&name) {
+            Some(value) => res.push_str(value),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("Undefined variable '{name}' in '{input}'"),
+                ))
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+/// Checks if the supplied string is a valid variable name: `[A-Za-z_][A-Za-z0-9_]*`
+/// # Arguments
+/// * `name` - The name to check
+pub fn is_variable_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Display for Variable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
+}
+
+impl FromStr for Variable {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.split_once('=') {
+            Some(v) => v,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Expected 'NAME=value' for variable, got '{s}'"),
+                ))
+            }
+        };
+
+        if value.contains(';') {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Value of variable '{}' contains ';', which separates variables",
+                    name.trim()
+                ),
+            ));
+        }
+
+        let name = name.trim();
+        if !is_variable_name(name) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid variable name '{name}'"),
+            ));
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            value: value.trim().to_owned(),
+        })
+    }
+}
+
+impl Serialize for Variable {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Variable {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn expand_references() {
+        let variables = vars(&[("A", "1"), ("B_2", "x")]);
+        assert_eq!(expand_str("$A-${B_2}.$A", &variables).unwrap(), "1-x.1");
+        assert_eq!(expand_str("$$A $ 5$", &variables).unwrap(), "$A $ 5$");
+        assert!(expand_str("$C", &variables).is_err());
+        assert!(expand_str("${A", &variables).is_err());
+    }
+
+    #[test]
+    fn parse_variable() {
+        let variable: Variable = " major = 1.2 ".parse().unwrap();
+        assert_eq!(variable.name, "major");
+        assert_eq!(variable.value, "1.2");
+        assert_eq!(variable.to_string(), "major=1.2");
+        assert!("1a=b".parse::<Variable>().is_err());
+        assert!("noequals".parse::<Variable>().is_err());
+        assert!("a=1 ; b=2".parse::<Variable>().is_err());
+    }
+
+    #[test]
+    fn resolve_declared_variables() {
+        let mut pkgbuild = PackageBuild::new("foo", "1.0.3", 2);
+        pkgbuild.vars = Some(vec![
+            "major=$PKG_VERSION_MAJOR".parse().unwrap(),
+            "srcname=$PKG_NAME-${major}.$PKG_VERSION_PATCH"
+                .parse()
+                .unwrap(),
+        ]);
+
+        let variables = pkgbuild.resolved_variables().unwrap();
+        assert_eq!(variables["PKG_REAL_VERSION"], "2");
+        assert_eq!(variables["major"], "1");
+        assert_eq!(variables["srcname"], "foo-1.3");
+
+        pkgbuild.vars = Some(vec!["PKG_NAME=bar".parse().unwrap()]);
+        assert!(pkgbuild.resolved_variables().is_err());
+
+        // Variables can only reference the ones declared before them
+        pkgbuild.vars = Some(vec!["a=$b".parse().unwrap(), "b=1".parse().unwrap()]);
+        assert!(pkgbuild.resolved_variables().is_err());
+    }
+
+    #[test]
+    fn expand_fields() {
+        let mut pkgbuild = PackageBuild::new("foo", "1.0", 0);
+        pkgbuild.vars = Some(vec!["srcname=foo-$PKG_VERSION".parse().unwrap()]);
+        pkgbuild.source = Some("https://example.org/${srcname}.tar.gz".to_owned());
+        pkgbuild.build = Some(vec!["cd $srcname".to_owned()]);
+
+        let expanded = pkgbuild.expand(&HashMap::new()).unwrap();
+        assert_eq!(
+            expanded.source.as_deref(),
+            Some("https://example.org/foo-1.0.tar.gz")
+        );
+        // Scripts are expanded by the shell
+        assert_eq!(expanded.build, pkgbuild.build);
+    }
+
+    #[test]
+    fn expand_dependencies() {
+        let mut pkgbuild = PackageBuild::new("foo", "1.0", 0);
+        pkgbuild.vars = Some(vec!["api=2".parse().unwrap()]);
+        pkgbuild.extra_dependencies = Some(vec!["foo-libs=$PKG_VERSION*".parse().unwrap()]);
+        pkgbuild.optional_dependencies = Some(vec!["foo-api$api: Sets $HOME and costs $$5"
+            .parse()
+            .unwrap()]);
+
+        let expanded = pkgbuild.expand(&HashMap::new()).unwrap();
+        assert_eq!(
+            expanded.extra_dependencies.unwrap()[0].to_string(),
+            "foo-libs=1.0*"
+        );
+        let optional = &expanded.optional_dependencies.unwrap()[0];
+        assert_eq!(optional.name, "foo-api2");
+        assert_eq!(optional.reason.as_deref(), Some("Sets $HOME and costs $$5"));
+
+        pkgbuild.extra_dependencies = Some(vec!["bar>=$undefined".parse().unwrap()]);
+        assert!(pkgbuild.expand(&HashMap::new()).is_err());
+    }
+}
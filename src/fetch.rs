@@ -0,0 +1,225 @@
+use crate::{BCError, StdIOErrorExt};
+use leaf::download;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Error, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// Fetches sources with specific URL schemes, see `FetcherRegistry`
+pub trait SourceFetcher {
+    /// Writes the contents of the source at the supplied URL to the output
+    /// # Arguments
+    /// * `url` - The URL of the source
+    /// * `output` - The output to write the contents to
+    fn fetch(&self, url: &str, output: &mut dyn Write) -> Result<(), BCError>;
+
+    /// Whether the fetched sources should be stored in the source cache,
+    /// sources that are available locally don't need to be
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Fetches `http://` and `https://` sources using leaf
+pub struct HttpFetcher;
+
+/// Fetches `file://` sources and plain paths, relative paths are relative to a base directory
+pub struct FileFetcher {
+    /// The directory relative paths are resolved against, the working directory if not set
+    pub base: Option<PathBuf>,
+}
+
+/// The fetchers to use for the URL schemes, sources without a scheme are plain paths
+/// and use the fetcher for `file`
+pub struct FetcherRegistry {
+    fetchers: HashMap<String, Box<dyn SourceFetcher>>,
+}
+
+impl FetcherRegistry {
+    /// Creates a registry with the built-in fetchers for `http`, `https` and `file`
+    pub fn new() -> Self {
+        let mut registry = Self {
+            fetchers: HashMap::new(),
+        };
+
+        registry.register("http", Box::new(HttpFetcher));
+        registry.register("https", Box::new(HttpFetcher));
+        registry.register("file", Box::new(FileFetcher::new(None)));
+
+        registry
+    }
+
+    /// Registers a fetcher for a URL scheme, replacing the previous one
+    /// # Arguments
+    /// * `scheme` - The scheme to register the fetcher for, such as `s3`
+    /// * `fetcher` - The fetcher to use for the scheme
+    pub fn register(&mut self, scheme: &str, fetcher: Box<dyn SourceFetcher>) {
+        self.fetchers.insert(scheme.to_lowercase(), fetcher);
+    }
+
+    /// Returns the fetcher for the scheme of the supplied URL
+    /// # Arguments
+    /// * `url` - The URL to get the fetcher for
+    pub fn get(&self, url: &str) -> Result<&dyn SourceFetcher, Error> {
+        let scheme = url_scheme(url).unwrap_or("file").to_lowercase();
+
+        match self.fetchers.get(&scheme) {
+            Some(fetcher) => Ok(fetcher.as_ref()),
+            None => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("No fetcher for the scheme '{scheme}' of source {url}"),
+            )),
+        }
+    }
+
+    /// Fetches the source at the supplied URL using the fetcher for its scheme
+    /// # Arguments
+    /// * `url` - The URL of the source
+    /// * `output` - The output to write the contents to
+    pub fn fetch(&self, url: &str, output: &mut dyn Write) -> Result<(), BCError> {
+        self.get(url)?.fetch(url, output)
+    }
+}
+
+impl Default for FetcherRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the scheme of the supplied URL (`<scheme>://...`), if it has one
+/// # Arguments
+/// * `url` - The URL to get the scheme of
+pub fn url_scheme(url: &str) -> Option<&str> {
+    url.split_once("://").map(|(scheme, _)| scheme)
+}
+
+impl SourceFetcher for HttpFetcher {
+    fn fetch(&self, url: &str, output: &mut dyn Write) -> Result<(), BCError> {
+        let mut write_res: Result<(), Error> = Ok(());
+
+        leaf::error::LErrorExt::err_prepend(
+            download::download(url, &format!("Downloading {url}"), true, |data| {
+                write_res = output.write_all(data);
+                write_res.is_ok()
+            }),
+            &format!("When fetching source from {url}"),
+        )?;
+        write_res.err_prepend("When writing out source file")?;
+
+        Ok(())
+    }
+}
+
+impl FileFetcher {
+    /// Creates a new file fetcher
+    /// # Arguments
+    /// * `base` - The directory relative paths are resolved against
+    pub fn new(base: Option<&Path>) -> Self {
+        Self {
+            base: base.map(|b| b.to_owned()),
+        }
+    }
+
+    /// Returns the path of the file the supplied URL points to
+    /// # Arguments
+    /// * `url` - The `file://` URL or path
+    pub fn path(&self, url: &str) -> PathBuf {
+        let path = Path::new(url.strip_prefix("file://").unwrap_or(url));
+
+        match &self.base {
+            Some(base) if path.is_relative() => base.join(path),
+            _ => path.to_owned(),
+        }
+    }
+}
+
+impl SourceFetcher for FileFetcher {
+    fn fetch(&self, url: &str, output: &mut dyn Write) -> Result<(), BCError> {
+        let path = self.path(url);
+        let mut file = File::open(&path)
+            .err_prepend(&format!("When opening source {}", path.to_string_lossy()))?;
+
+        std::io::copy(&mut file, output)
+            .err_prepend(&format!("When reading source {}", path.to_string_lossy()))?;
+
+        Ok(())
+    }
+
+    fn cacheable(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StaticFetcher(&'static [u8]);
+
+    impl SourceFetcher for StaticFetcher {
+        fn fetch(&self, _url: &str, output: &mut dyn Write) -> Result<(), BCError> {
+            output.write_all(self.0)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn schemes() {
+        assert_eq!(url_scheme("https://example.org/a"), Some("https"));
+        assert_eq!(url_scheme("s3://bucket/a"), Some("s3"));
+        assert_eq!(url_scheme("patches/a.patch"), None);
+    }
+
+    #[test]
+    fn builtin_fetchers() {
+        let registry = FetcherRegistry::new();
+        assert!(registry.get("https://example.org/a").unwrap().cacheable());
+        assert!(registry.get("HTTP://example.org/a").unwrap().cacheable());
+        assert!(!registry.get("file:///tmp/a").unwrap().cacheable());
+        assert!(!registry.get("patches/a.patch").unwrap().cacheable());
+        assert!(registry.get("s3://bucket/a").is_err());
+    }
+
+    #[test]
+    fn custom_fetchers() {
+        let mut registry = FetcherRegistry::new();
+        registry.register("S3", Box::new(StaticFetcher(b"s3")));
+        registry.register("https", Box::new(StaticFetcher(b"mirror")));
+
+        let mut output = Vec::new();
+        registry.fetch("s3://bucket/a", &mut output).unwrap();
+        assert_eq!(output, b"s3");
+
+        let mut output = Vec::new();
+        registry
+            .fetch("https://example.org/a", &mut output)
+            .unwrap();
+        assert_eq!(output, b"mirror");
+    }
+
+    #[test]
+    fn file_fetcher() {
+        let dir = std::env::temp_dir().join(format!("pkgbuild-fetch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), b"abc").unwrap();
+
+        let fetcher = FileFetcher::new(Some(&dir));
+        assert_eq!(fetcher.path("a.txt"), dir.join("a.txt"));
+        assert_eq!(fetcher.path("file:///etc/hosts"), Path::new("/etc/hosts"));
+
+        let mut output = Vec::new();
+        fetcher.fetch("a.txt", &mut output).unwrap();
+        assert_eq!(output, b"abc");
+
+        let url = format!("file://{}", dir.join("a.txt").to_string_lossy());
+        let mut output = Vec::new();
+        FetcherRegistry::new().fetch(&url, &mut output).unwrap();
+        assert_eq!(output, b"abc");
+
+        assert!(fetcher.fetch("missing.txt", &mut Vec::new()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,44 +1,79 @@
 #[macro_use]
 extern crate log;
 
+mod arch;
+pub use arch::*;
 mod build_context;
 pub use build_context::*;
+mod build_system;
+pub use build_system::*;
+mod checksum;
+pub use checksum::*;
 mod config;
 pub use config::*;
+mod dependency;
+pub use dependency::*;
 mod error;
 pub use error::*;
+mod expand;
+pub use expand::*;
+mod fetch;
+pub use fetch::*;
+pub mod lint;
 pub mod mount;
 pub mod parser;
+mod source;
+pub use source::*;
+mod split;
+pub use split::*;
 pub mod util;
+mod validate;
+pub use validate::*;
+mod version;
+pub use version::*;
 
 use serde::{de::*, *};
-use std::{fmt::Display, str::FromStr};
+use std::{fmt::Display, path::PathBuf, str::FromStr};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct PackageBuild {
     pub name: String,
     pub version: String,
     #[serde(deserialize_with = "crate::deserialize_number_from_string")]
     pub real_version: u32,
+    #[serde(default)]
+    pub epoch: Option<u32>,
+    pub inherit: Option<String>,
 
     pub maintainer: Option<String>,
     pub maintainer_email: Option<String>,
     pub description: Option<String>,
     pub provides: Option<Vec<String>>,
+    pub arch: Option<Vec<String>>,
     pub source: Option<String>,
+    pub source_sha256: Option<String>,
     pub extra_sources: Option<Vec<String>>,
-    pub extra_dependencies: Option<Vec<String>>,
-    pub optional_dependencies: Option<Vec<String>>,
-    pub build_dependencies: Option<Vec<String>>,
-    pub cross_dependencies: Option<Vec<String>>,
+    pub extra_sources_sha256: Option<Vec<String>>,
+    pub extra_dependencies: Option<Vec<Dependency>>,
+    pub optional_dependencies: Option<Vec<Dependency>>,
+    pub build_dependencies: Option<Vec<Dependency>>,
+    pub cross_dependencies: Option<Vec<Dependency>>,
     pub preinstall: Option<String>,
     pub postinstall: Option<String>,
     pub strip: Option<bool>,
+    pub build_system: Option<BuildSystem>,
+    pub split_packages: Option<Vec<SplitPackage>>,
+    pub arch_overrides: Option<Vec<ArchOverride>>,
+    pub vars: Option<Vec<Variable>>,
 
     pub prepare: Option<Vec<String>>,
     pub build: Option<Vec<String>>,
     pub check: Option<Vec<String>>,
     pub package: Option<Vec<String>>,
+
+    /// The directory the packagebuild was loaded from, relative sources resolve against it
+    #[serde(skip)]
+    pub origin: Option<PathBuf>,
 }
 
 impl PackageBuild {
@@ -47,13 +82,18 @@ impl PackageBuild {
             name: name.to_owned(),
             version: version.to_owned(),
             real_version,
+            epoch: None,
+            inherit: None,
 
             maintainer: None,
             maintainer_email: None,
             description: None,
             provides: None,
+            arch: None,
             source: None,
+            source_sha256: None,
             extra_sources: None,
+            extra_sources_sha256: None,
             extra_dependencies: None,
             optional_dependencies: None,
             build_dependencies: None,
@@ -61,11 +101,17 @@ impl PackageBuild {
             preinstall: None,
             postinstall: None,
             strip: None,
+            build_system: None,
+            split_packages: None,
+            arch_overrides: None,
+            vars: None,
 
             prepare: None,
             build: None,
             check: None,
             package: None,
+
+            origin: None,
         }
     }
 }
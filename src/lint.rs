@@ -0,0 +1,223 @@
+//! Lints for packagebuilds, producing diagnostics with a severity and a location
+
+mod rules;
+pub use rules::*;
+
+use crate::PackageBuild;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::Display;
+
+/// The severity of a diagnostic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// The location within a packagebuild a diagnostic refers to
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Location {
+    /// The packagebuild as a whole
+    PackageBuild,
+    /// A field of the packagebuild
+    Field(String),
+    /// A line (starting at 0) within the script of a phase
+    Script { phase: String, line: usize },
+}
+
+/// A diagnostic produced by a lint rule
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Diagnostic {
+    /// The name of the rule that produced this diagnostic
+    pub rule: String,
+    /// The severity of the diagnostic
+    pub severity: Severity,
+    /// The location the diagnostic refers to
+    pub location: Location,
+    /// A human readable message
+    pub message: String,
+}
+
+/// A lint rule that can check a packagebuild
+pub trait Rule {
+    /// The unique name of the rule, used for enabling and disabling it
+    fn name(&self) -> &'static str;
+
+    /// Checks the supplied packagebuild and returns all diagnostics
+    /// # Arguments
+    /// * `pkgbuild` - The packagebuild to check
+    fn check(&self, pkgbuild: &PackageBuild) -> Vec<Diagnostic>;
+}
+
+/// A linter running a set of rules over packagebuilds
+pub struct Linter {
+    rules: Vec<Box<dyn Rule>>,
+    disabled: HashSet<String>,
+}
+
+impl Linter {
+    /// Creates a new linter with all the built-in rules enabled
+    pub fn new() -> Self {
+        Self {
+            rules: default_rules(),
+            disabled: HashSet::new(),
+        }
+    }
+
+    /// Adds a custom rule to the linter
+    /// # Arguments
+    /// * `rule` - The rule to add
+    pub fn add_rule(&mut self, rule: Box<dyn Rule>) {
+        self.rules.push(rule);
+    }
+
+    /// Enables a rule by its name
+    /// # Arguments
+    /// * `name` - The name of the rule
+    pub fn enable(&mut self, name: &str) {
+        self.disabled.remove(name);
+    }
+
+    /// Disables a rule by its name
+    /// # Arguments
+    /// * `name` - The name of the rule
+    pub fn disable(&mut self, name: &str) {
+        self.disabled.insert(name.to_owned());
+    }
+
+    /// Returns the names of all known rules and whether they are enabled
+    pub fn rules(&self) -> Vec<(&'static str, bool)> {
+        self.rules
+            .iter()
+            .map(|r| (r.name(), !self.disabled.contains(r.name())))
+            .collect()
+    }
+
+    /// Runs all enabled rules over the supplied packagebuild
+    /// # Arguments
+    /// * `pkgbuild` - The packagebuild to lint
+    pub fn lint(&self, pkgbuild: &PackageBuild) -> Vec<Diagnostic> {
+        self.rules
+            .iter()
+            .filter(|r| !self.disabled.contains(r.name()))
+            .flat_map(|r| r.check(pkgbuild))
+            .collect()
+    }
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Diagnostic {
+    /// Creates a new diagnostic
+    /// # Arguments
+    /// * `rule` - The name of the rule producing the diagnostic
+    /// * `severity` - The severity of the diagnostic
+    /// * `location` - The location the diagnostic refers to
+    /// * `message` - The message for the diagnostic
+    pub fn new(rule: &str, severity: Severity, location: Location, message: &str) -> Self {
+        Self {
+            rule: rule.to_owned(),
+            severity,
+            location,
+            message: message.to_owned(),
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Info => write!(f, "info"),
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PackageBuild => write!(f, "packagebuild"),
+            Self::Field(field) => write!(f, "{field}"),
+            Self::Script { phase, line } => write!(f, "{phase}:{}", line + 1),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} [{}] {}: {}",
+            self.severity, self.rule, self.location, self.message
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct AlwaysFails;
+
+    impl Rule for AlwaysFails {
+        fn name(&self) -> &'static str {
+            "always_fails"
+        }
+
+        fn check(&self, _pkgbuild: &PackageBuild) -> Vec<Diagnostic> {
+            vec![Diagnostic::new(
+                self.name(),
+                Severity::Error,
+                Location::PackageBuild,
+                "Fails",
+            )]
+        }
+    }
+
+    fn rules(diagnostics: &[Diagnostic]) -> Vec<&str> {
+        diagnostics.iter().map(|d| d.rule.as_str()).collect()
+    }
+
+    #[test]
+    fn enable_and_disable_rules() {
+        let pkgbuild = PackageBuild::new("foo", "1.0", 0);
+        let mut linter = Linter::new();
+        assert!(rules(&linter.lint(&pkgbuild)).contains(&"missing_description"));
+
+        linter.disable("missing_description");
+        assert!(!rules(&linter.lint(&pkgbuild)).contains(&"missing_description"));
+        assert!(linter.rules().contains(&("missing_description", false)));
+        assert!(linter.rules().contains(&("missing_maintainer", true)));
+
+        linter.enable("missing_description");
+        assert!(rules(&linter.lint(&pkgbuild)).contains(&"missing_description"));
+        assert!(linter.rules().iter().all(|(_, enabled)| *enabled));
+    }
+
+    #[test]
+    fn custom_rules() {
+        let pkgbuild = PackageBuild::new("foo", "1.0", 0);
+        let mut linter = Linter::new();
+        linter.add_rule(Box::new(AlwaysFails));
+
+        let diagnostics = linter.lint(&pkgbuild);
+        let failure = diagnostics
+            .iter()
+            .find(|d| d.rule == "always_fails")
+            .unwrap();
+        assert_eq!(
+            failure.to_string(),
+            "error [always_fails] packagebuild: Fails"
+        );
+
+        linter.disable("always_fails");
+        assert!(!rules(&linter.lint(&pkgbuild)).contains(&"always_fails"));
+    }
+}
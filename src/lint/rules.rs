@@ -0,0 +1,535 @@
+//! The built-in lint rules
+
+use super::{Diagnostic, Location, Rule, Severity};
+use crate::util::is_email_address;
+use crate::PackageBuild;
+
+/// Returns instances of all the built-in rules
+pub fn default_rules() -> Vec<Box<dyn Rule>> {
+    vec![
+        Box::new(MissingDescription),
+        Box::new(MissingMaintainer),
+        Box::new(InvalidEmail),
+        Box::new(UnversionedSource),
+        Box::new(RepeatedCd),
+        Box::new(WriteOutsideInstallDir),
+        Box::new(NetworkInPhase),
+        Box::new(EmptyPackagePhase),
+    ]
+}
+
+/// Warns if the packagebuild has no description
+pub struct MissingDescription;
+
+/// Warns if the packagebuild has no maintainer or maintainer email
+pub struct MissingMaintainer;
+
+/// Errors if the maintainer email is not an email address
+pub struct InvalidEmail;
+
+/// Warns if the source does not use `$PKG_VERSION`
+pub struct UnversionedSource;
+
+/// Informs about the same `cd` command starting every phase
+pub struct RepeatedCd;
+
+/// Warns about commands in any script writing outside of `$PKG_INSTALL_DIR`
+pub struct WriteOutsideInstallDir;
+
+/// Warns about network access (`curl`, `wget`) within any script
+pub struct NetworkInPhase;
+
+/// Warns if the `package` phase is missing or empty
+pub struct EmptyPackagePhase;
+
+impl Rule for MissingDescription {
+    fn name(&self) -> &'static str {
+        "missing_description"
+    }
+
+    fn check(&self, pkgbuild: &PackageBuild) -> Vec<Diagnostic> {
+        match &pkgbuild.description {
+            Some(d) if !d.trim().is_empty() => vec![],
+            _ => vec![Diagnostic::new(
+                self.name(),
+                Severity::Warning,
+                Location::Field("description".to_owned()),
+                "The packagebuild has no description",
+            )],
+        }
+    }
+}
+
+impl Rule for MissingMaintainer {
+    fn name(&self) -> &'static str {
+        "missing_maintainer"
+    }
+
+    fn check(&self, pkgbuild: &PackageBuild) -> Vec<Diagnostic> {
+        let mut res = Vec::new();
+
+        if pkgbuild.maintainer.is_none() {
+            res.push(Diagnostic::new(
+                self.name(),
+                Severity::Warning,
+                Location::Field("maintainer".to_owned()),
+                "The packagebuild has no maintainer",
+            ));
+        }
+
+        if pkgbuild.maintainer_email.is_none() {
+            res.push(Diagnostic::new(
+                self.name(),
+                Severity::Warning,
+                Location::Field("maintainer_email".to_owned()),
+                "The packagebuild has no maintainer email",
+            ));
+        }
+
+        res
+    }
+}
+
+impl Rule for InvalidEmail {
+    fn name(&self) -> &'static str {
+        "invalid_email"
+    }
+
+    fn check(&self, pkgbuild: &PackageBuild) -> Vec<Diagnostic> {
+        match &pkgbuild.maintainer_email {
+            Some(email) if !is_email_address(email) => vec![Diagnostic::new(
+                self.name(),
+                Severity::Error,
+                Location::Field("maintainer_email".to_owned()),
+                &format!("'{email}' is not a valid email address"),
+            )],
+            _ => vec![],
+        }
+    }
+}
+
+impl Rule for UnversionedSource {
+    fn name(&self) -> &'static str {
+        "unversioned_source"
+    }
+
+    fn check(&self, pkgbuild: &PackageBuild) -> Vec<Diagnostic> {
+        match &pkgbuild.source {
+            Some(source)
+                if !source.contains("$PKG_VERSION") && !source.contains("${PKG_VERSION}") =>
+            {
+                vec![Diagnostic::new(
+                    self.name(),
+                    Severity::Warning,
+                    Location::Field("source".to_owned()),
+                    "The source does not use $PKG_VERSION, it has to be updated on every version bump",
+                )]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+impl Rule for RepeatedCd {
+    fn name(&self) -> &'static str {
+        "repeated_cd"
+    }
+
+    fn check(&self, pkgbuild: &PackageBuild) -> Vec<Diagnostic> {
+        let phases = phases(pkgbuild);
+        if phases.len() < 2 {
+            return vec![];
+        }
+
+        let first_lines: Vec<Option<&String>> = phases.iter().map(|p| p.1.first()).collect();
+        match first_lines[0] {
+            Some(line)
+                if line.starts_with("cd ") && first_lines.iter().all(|l| *l == Some(line)) =>
+            {
+                vec![Diagnostic::new(
+                    self.name(),
+                    Severity::Info,
+                    Location::PackageBuild,
+                    &format!("'{line}' is repeated at the start of every phase"),
+                )]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+impl Rule for WriteOutsideInstallDir {
+    fn name(&self) -> &'static str {
+        "write_outside_install_dir"
+    }
+
+    fn check(&self, pkgbuild: &PackageBuild) -> Vec<Diagnostic> {
+        let mut res = Vec::new();
+
+        for (phase, script) in scripts(pkgbuild) {
+            for (i, line) in script.iter().enumerate() {
+                let location = Location::Script {
+                    phase: phase.clone(),
+                    line: i,
+                };
+
+                for command in commands(line) {
+                    if let Some(target) = outside_write(&command) {
+                        res.push(Diagnostic::new(
+                            self.name(),
+                            Severity::Warning,
+                            location.clone(),
+                            &format!("'{target}' is written outside of $PKG_INSTALL_DIR"),
+                        ));
+                    }
+                }
+
+                let installs = ["make install", "ninja install", "cmake --install"]
+                    .iter()
+                    .any(|c| line.contains(c));
+                if installs && !line.contains("DESTDIR") && !line.contains("PKG_INSTALL_DIR") {
+                    res.push(Diagnostic::new(
+                        self.name(),
+                        Severity::Warning,
+                        location.clone(),
+                        "Installing without DESTDIR installs outside of $PKG_INSTALL_DIR",
+                    ));
+                }
+            }
+        }
+
+        res
+    }
+}
+
+impl Rule for NetworkInPhase {
+    fn name(&self) -> &'static str {
+        "network_in_phase"
+    }
+
+    fn check(&self, pkgbuild: &PackageBuild) -> Vec<Diagnostic> {
+        let mut res = Vec::new();
+
+        for (phase, script) in scripts(pkgbuild) {
+            for (i, line) in script.iter().enumerate() {
+                for command in commands(line) {
+                    if let Some(tool @ ("curl" | "wget")) = command.first().map(|w| w.as_str()) {
+                        res.push(Diagnostic::new(
+                            self.name(),
+                            Severity::Warning,
+                            Location::Script {
+                                phase: phase.clone(),
+                                line: i,
+                            },
+                            &format!("'{tool}' accesses the network, use sources instead"),
+                        ));
+                    }
+                }
+            }
+        }
+
+        res
+    }
+}
+
+impl Rule for EmptyPackagePhase {
+    fn name(&self) -> &'static str {
+        "empty_package_phase"
+    }
+
+    fn check(&self, pkgbuild: &PackageBuild) -> Vec<Diagnostic> {
+        match &pkgbuild.apply_build_system().package {
+            Some(script) if !script.is_empty() => vec![],
+            _ => vec![Diagnostic::new(
+                self.name(),
+                Severity::Warning,
+                Location::Field("package".to_owned()),
+                "The package phase is empty, the package will not contain any files",
+            )],
+        }
+    }
+}
+
+/// Returns all the phases of a packagebuild that are present
+/// # Arguments
+/// * `pkgbuild` - The packagebuild to get the phases of
+fn phases(pkgbuild: &PackageBuild) -> Vec<(&'static str, &Vec<String>)> {
+    [
+        ("prepare", &pkgbuild.prepare),
+        ("build", &pkgbuild.build),
+        ("check", &pkgbuild.check),
+        ("package", &pkgbuild.package),
+    ]
+    .into_iter()
+    .filter_map(|(name, script)| script.as_ref().map(|s| (name, s)))
+    .collect()
+}
+
+/// Returns all the scripts of a packagebuild that are present together with their keys:
+/// The phases, the `package_<name>` scripts of the split packages and the
+/// `<phase>_<arch>` overrides
+/// # Arguments
+/// * `pkgbuild` - The packagebuild to get the scripts of
+fn scripts(pkgbuild: &PackageBuild) -> Vec<(String, &Vec<String>)> {
+    let mut scripts: Vec<(String, &Vec<String>)> = phases(pkgbuild)
+        .into_iter()
+        .map(|(phase, script)| (phase.to_owned(), script))
+        .collect();
+
+    for split in pkgbuild.split_packages.iter().flatten() {
+        if let Some(script) = &split.package {
+            scripts.push((format!("package_{}", split.name), script));
+        }
+    }
+
+    for o in pkgbuild.arch_overrides.iter().flatten() {
+        for (phase, script) in [
+            ("prepare", &o.prepare),
+            ("build", &o.build),
+            ("check", &o.check),
+            ("package", &o.package),
+        ] {
+            if let Some(script) = script {
+                scripts.push((format!("{phase}_{}", o.arch), script));
+            }
+        }
+    }
+
+    scripts
+}
+
+/// Splits a script line into its simple commands, each being a list of unquoted words.
+///
+/// Quotes and backslashes are respected, so separators and `#` within them are part
+/// of the words. Leading variable assignments are skipped, redirections are kept as words.
+/// # Arguments
+/// * `line` - The line to split
+fn commands(line: &str) -> Vec<Vec<String>> {
+    let mut commands: Vec<Vec<String>> = Vec::new();
+    let mut words: Vec<String> = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                if let Some(c) = chars.next() {
+                    word.get_or_insert_with(String::new).push(c);
+                }
+            }
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, '#') if word.is_none() => break,
+            (None, ';' | '&' | '|') => {
+                words.extend(word.take());
+                commands.push(std::mem::take(&mut words));
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word.take());
+    commands.push(words);
+
+    commands
+        .into_iter()
+        .map(|command| {
+            command
+                .into_iter()
+                .skip_while(|word| word.contains('=') && !word.starts_with('-'))
+                .collect::<Vec<String>>()
+        })
+        .filter(|words| !words.is_empty())
+        .collect()
+}
+
+/// Returns the absolute path a command writes to if it is outside of the allowed directories
+/// # Arguments
+/// * `command` - The words of the command
+fn outside_write(command: &[String]) -> Option<&str> {
+    let is_outside = |path: &str| {
+        path.starts_with('/')
+            && !["/build", "/tmp", "/target", "/split", "/dev/null"]
+                .iter()
+                .any(|allowed| path == *allowed || path.starts_with(&format!("{allowed}/")))
+    };
+
+    // Redirections write to their target
+    for (i, word) in command.iter().enumerate() {
+        if let Some(target) = word.trim_start_matches('2').strip_prefix('>') {
+            let target = match target.trim_start_matches('>') {
+                "" => command.get(i + 1).map(|w| w.as_str()).unwrap_or_default(),
+                t => t,
+            };
+            if is_outside(target) {
+                return Some(target);
+            }
+        }
+    }
+
+    let args: Vec<&str> = command[1..]
+        .iter()
+        .map(|w| w.as_str())
+        .take_while(|w| !w.starts_with('>'))
+        .filter(|w| !w.starts_with('-'))
+        .collect();
+
+    let targets: Vec<&str> = match command[0].as_str() {
+        // These write to their last argument
+        "cp" | "mv" | "install" | "ln" => args.last().copied().into_iter().collect(),
+        // These write to all of their arguments
+        "mkdir" | "touch" | "tee" | "rm" | "chmod" | "chown" => args,
+        _ => vec![],
+    };
+
+    targets.into_iter().find(|t| is_outside(t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn pkgbuild(fields: &str) -> PackageBuild {
+        parse(&mut format!("name=foo\nversion=1.0\nreal_version=0\n{fields}").as_bytes()).unwrap()
+    }
+
+    fn check(rule: &dyn Rule, fields: &str) -> Vec<Diagnostic> {
+        rule.check(&pkgbuild(fields))
+    }
+
+    fn script_lines(rule: &dyn Rule, fields: &str) -> Vec<String> {
+        check(rule, fields)
+            .into_iter()
+            .map(|d| d.location.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn missing_description() {
+        assert_eq!(check(&MissingDescription, "").len(), 1);
+        assert_eq!(check(&MissingDescription, "description=Foo").len(), 0);
+    }
+
+    #[test]
+    fn missing_maintainer() {
+        assert_eq!(check(&MissingMaintainer, "").len(), 2);
+        assert_eq!(check(&MissingMaintainer, "maintainer=Someone").len(), 1);
+        let fields = "maintainer=Someone\nmaintainer_email=someone@example.org";
+        assert_eq!(check(&MissingMaintainer, fields).len(), 0);
+    }
+
+    #[test]
+    fn invalid_email() {
+        let diagnostics = check(&InvalidEmail, "maintainer_email=someone");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(
+            check(&InvalidEmail, "maintainer_email=someone@example.org").len(),
+            0
+        );
+        assert_eq!(check(&InvalidEmail, "").len(), 0);
+    }
+
+    #[test]
+    fn unversioned_source() {
+        let source = |s: &str| check(&UnversionedSource, &format!("source={s}")).len();
+        assert_eq!(source("https://example.org/foo-1.0.tar.gz"), 1);
+        assert_eq!(source("https://example.org/foo-$PKG_VERSION.tar.gz"), 0);
+        assert_eq!(source("https://example.org/foo-${PKG_VERSION}.tar.gz"), 0);
+        assert_eq!(check(&UnversionedSource, "").len(), 0);
+    }
+
+    #[test]
+    fn repeated_cd() {
+        let repeated = "prepare={\n  cd src\n}\nbuild={\n  cd src\n  make\n}";
+        assert_eq!(check(&RepeatedCd, repeated).len(), 1);
+
+        let different = "prepare={\n  cd src\n}\nbuild={\n  cd build\n}";
+        assert_eq!(check(&RepeatedCd, different).len(), 0);
+        assert_eq!(check(&RepeatedCd, "build={\n  cd src\n}").len(), 0);
+    }
+
+    #[test]
+    fn write_outside_install_dir() {
+        let rule = &WriteOutsideInstallDir;
+        assert_eq!(
+            script_lines(rule, "package={\n  make\n  cp foo /usr/bin/foo\n}"),
+            vec!["package:2"]
+        );
+        assert_eq!(
+            script_lines(rule, "package={\n  echo a > /etc/foo.conf\n}"),
+            vec!["package:1"]
+        );
+        assert_eq!(
+            script_lines(rule, "package={\n  make install\n}"),
+            vec!["package:1"]
+        );
+
+        let fields = "package={\n  cp foo $PKG_INSTALL_DIR/usr/bin\n  \
+                      make DESTDIR=$PKG_INSTALL_DIR install\n  mkdir -p /tmp/x\n  \
+                      echo \"> /etc/foo; rm /usr\" | tee $PKG_ROOT/log\n}";
+        assert!(check(rule, fields).is_empty());
+    }
+
+    #[test]
+    fn network_in_phase() {
+        let rule = &NetworkInPhase;
+        assert_eq!(
+            script_lines(rule, "prepare={\n  curl -O https://example.org/a\n}"),
+            vec!["prepare:1"]
+        );
+        assert_eq!(
+            script_lines(
+                rule,
+                "build={\n  make && FOO=1 wget https://example.org/a\n}"
+            ),
+            vec!["build:1"]
+        );
+        assert!(check(rule, "build={\n  echo 'no ; curl' # wget\n  ./curl\n}").is_empty());
+    }
+
+    #[test]
+    fn empty_package_phase() {
+        assert_eq!(check(&EmptyPackagePhase, "").len(), 1);
+        assert_eq!(
+            check(&EmptyPackagePhase, "package={\n  make install\n}").len(),
+            0
+        );
+        assert_eq!(check(&EmptyPackagePhase, "build_system=cmake").len(), 0);
+    }
+
+    #[test]
+    fn split_and_arch_scripts() {
+        let fields = "arch=[x86_64]\nsplit_packages=[foo-dev]\n\
+                      package_foo-dev={\n  cp a.h /usr/include\n}\n\
+                      build_x86_64={\n  make\n  wget https://example.org/a\n}";
+
+        assert_eq!(
+            script_lines(&WriteOutsideInstallDir, fields),
+            vec!["package_foo-dev:1"]
+        );
+        assert_eq!(
+            script_lines(&NetworkInPhase, fields),
+            vec!["build_x86_64:2"]
+        );
+    }
+
+    #[test]
+    fn split_commands() {
+        assert_eq!(
+            commands(r#"A=1 make -j4 && echo 'a; b' "c|d" e\;f; ls # rm -rf /"#),
+            vec![
+                vec!["make", "-j4"],
+                vec!["echo", "a; b", "c|d", "e;f"],
+                vec!["ls"]
+            ]
+        );
+        assert!(commands("# comment").is_empty());
+    }
+}
@@ -1,113 +1,1052 @@
+mod document;
+pub use document::Document;
+mod format;
+pub use format::format;
 mod multiline;
+mod template;
+pub use template::TemplateResolver;
 pub mod util;
+mod writer;
+pub use writer::write;
 
-use crate::PackageBuild;
+use crate::{
+    check_name, split_inline_checksum, ArchOverride, Dependency, PackageBuild, SplitPackage,
+    StdIOErrorExt, Variable, CHECKSUM_SKIP,
+};
 use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::{self, File};
 use std::io::{Error, ErrorKind, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use util::GetExt;
 
+/// All keys known to the packagebuild format, in their canonical order
+pub const KEYS: &[&str] = &[
+    "name",
+    "version",
+    "real_version",
+    "epoch",
+    "inherit",
+    "maintainer",
+    "maintainer_email",
+    "description",
+    "provides",
+    "arch",
+    "source",
+    "source_sha256",
+    "extra_sources",
+    "extra_sources_sha256",
+    "extra_dependencies",
+    "optional_dependencies",
+    "build_dependencies",
+    "cross_dependencies",
+    "preinstall",
+    "postinstall",
+    "strip",
+    "build_system",
+    "split_packages",
+    "vars",
+    "prepare",
+    "build",
+    "check",
+    "package",
+];
+
+/// The keys of a split package, used as `<key>_<split package name>`
+pub const SPLIT_KEYS: &[&str] = &[
+    "description",
+    "provides",
+    "extra_dependencies",
+    "optional_dependencies",
+    "files",
+    "package",
+];
+
+/// The keys that can be overridden per architecture, used as `<key>_<arch>`
+pub const ARCH_KEYS: &[&str] = &[
+    "source",
+    "source_sha256",
+    "extra_dependencies",
+    "optional_dependencies",
+    "build_dependencies",
+    "cross_dependencies",
+    "prepare",
+    "build",
+    "check",
+    "package",
+];
+
 ///A parse result can either be a normal string or a vector of strings
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum ParseResult {
     String(String),
     Vec(Vec<String>),
 }
 
-///Parses the packagebuild contents line by line
+/// An entry of a packagebuild and the lines it spans
+#[derive(Debug, Clone)]
+struct Entry {
+    /// The key of the entry
+    key: String,
+    /// The parsed value, `None` if the value is empty
+    value: Option<ParseResult>,
+    /// The range of lines the entry spans
+    lines: Range<usize>,
+}
+
+///Scans the packagebuild contents line by line for entries
+///
+/// Lines starting with `#` (after leading whitespace) are comments and get skipped,
+/// values can be followed by a comment, see `util::split_comment()`.
+/// Within `{}` blocks, every line is passed to the shell unchanged, so comments
+/// there are handled by the shell itself.
 /// # Arguments
-/// * `lines` - A Vec containing all the lines
-fn parse_pkgbuild(lines: Vec<&str>) -> Result<HashMap<String, ParseResult>, Error> {
+/// * `lines` - A slice containing all the lines
+fn scan_pkgbuild(lines: &[&str]) -> Result<Vec<Entry>, Error> {
     //Create the return and an iterator over the lines
-    let mut map: HashMap<String, ParseResult> = HashMap::new();
+    let mut entries: Vec<Entry> = Vec::new();
     let mut iter = lines.iter();
 
     //Iterate over the lines
     while let Some(line) = iter.next() {
+        let start = lines.len() - iter.as_slice().len() - 1;
+
+        //Skip comment lines
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+
         //If we can split the line by `=`
         if let Some(v) = line.split_once('=') {
-            //Trim the key and the contents
+            //Trim the key and the contents, blocks leave comments to the shell
             let key = v.0.trim().to_string();
-            let data = v.1.trim().to_string();
-            let ob = util::char_occurrences(&data, '{');
+            let data = if v.1.trim_start().starts_with('{') {
+                v.1.trim().to_string()
+            } else {
+                util::split_comment(v.1).0.trim().to_string()
+            };
 
-            //Ignore empty data
-            if data.is_empty() {
-                continue;
+            let value = if data.is_empty() {
+                None
             }
-
             //If there is an array to parse
-            if data.starts_with('[') {
-                let res = util::parse_array(&data)?;
-                if !res.is_empty() {
-                    map.insert(key, ParseResult::Vec(res));
-                }
+            else if data.starts_with('[') {
+                Some(ParseResult::Vec(
+                    util::parse_array(&data).err_prepend(&format!("On line {}", start + 1))?,
+                ))
             }
-            // If there is no opening brace, use the data as-is
-            else if ob.is_empty() {
-                map.insert(key, ParseResult::String(v.1.trim().to_string()));
+            //If there is a block to parse
+            else if data.starts_with('{') {
+                Some(ParseResult::Vec(
+                    multiline::parse_multiline(&mut iter, &data)
+                        .err_prepend(&format!("On line {}", start + 1))?,
+                ))
             }
-            //Else, parse multiline
+            //Else, use the data as-is
             else {
-                let multiline = multiline::parse_multiline(&mut iter, &data)?;
-                if !multiline.is_empty() {
-                    map.insert(key, ParseResult::Vec(multiline));
-                }
+                Some(ParseResult::String(data))
+            };
+
+            entries.push(Entry {
+                key,
+                value,
+                lines: start..lines.len() - iter.as_slice().len(),
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+/// How strict the parser is about the keys and values of a packagebuild
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// Unknown and duplicate keys and invalid values are returned as warnings
+    #[default]
+    Lenient,
+    /// Unknown and duplicate keys and invalid values are errors
+    Strict,
+}
+
+/// The kinds of problems the parser can warn about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseWarningKind {
+    /// The key is not known to the packagebuild format
+    UnknownKey,
+    /// The key has already been defined on the contained line (starting at 1),
+    /// the later definition wins
+    DuplicateKey(usize),
+    /// The key overrides a value for the contained architecture, which is not declared in `arch`
+    UndeclaredArch(String),
+    /// The value does not fit the key, the field is left unset.
+    /// Contains a description of the problem
+    InvalidValue(String),
+}
+
+/// A warning about a key of the packagebuild
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWarning {
+    /// The line (starting at 1) of the offending key
+    pub line: usize,
+    /// The offending key
+    pub key: String,
+    /// The kind of warning
+    pub kind: ParseWarningKind,
+}
+
+/// Returns whether the supplied key is known to the packagebuild format
+/// # Arguments
+/// * `key` - The key to check
+/// * `splits` - The names of the declared split packages
+/// * `archs` - The declared architectures
+fn is_known_key(key: &str, splits: &[String], archs: &[String]) -> bool {
+    KEYS.contains(&key)
+        || is_suffixed_key(key, SPLIT_KEYS, splits)
+        || is_suffixed_key(key, ARCH_KEYS, archs)
+}
+
+/// Returns whether the supplied key is one of the keys in the form `<key>_<suffix>`
+/// # Arguments
+/// * `key` - The key to check
+/// * `keys` - The keys that can be suffixed
+/// * `suffixes` - The allowed suffixes
+fn is_suffixed_key(key: &str, keys: &[&str], suffixes: &[String]) -> bool {
+    keys.iter().any(|k| {
+        key.strip_prefix(k)
+            .and_then(|rest| rest.strip_prefix('_'))
+            .is_some_and(|suffix| suffixes.iter().any(|s| s == suffix))
+    })
+}
+
+/// Returns the architecture of a `<key>_<arch>` key, using the longest matching key
+/// # Arguments
+/// * `key` - The key to split
+fn undeclared_arch(key: &str) -> Option<&str> {
+    ARCH_KEYS
+        .iter()
+        .filter_map(|k| {
+            key.strip_prefix(k)?
+                .strip_prefix('_')
+                .map(|arch| (k.len(), arch))
+        })
+        .filter(|(_, arch)| !arch.is_empty())
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, arch)| arch)
+}
+
+/// Checks the scanned entries for unknown and duplicate keys
+/// # Arguments
+/// * `entries` - The entries to check
+fn check_keys(entries: &[Entry]) -> Vec<ParseWarning> {
+    let mut warnings: Vec<ParseWarning> = Vec::new();
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+
+    // The keys of split packages and architectures are only known if they are declared
+    let declared = |key: &str| match entries.iter().rev().find(|e| e.key == key) {
+        Some(Entry {
+            value: Some(ParseResult::Vec(names)),
+            ..
+        }) => names.clone(),
+        _ => Vec::new(),
+    };
+    let splits = declared("split_packages");
+    let archs = declared("arch");
+
+    for entry in entries {
+        let line = entry.lines.start + 1;
+
+        if !is_known_key(&entry.key, &splits, &archs) {
+            warnings.push(ParseWarning {
+                line,
+                key: entry.key.clone(),
+                kind: match undeclared_arch(&entry.key) {
+                    Some(arch) => ParseWarningKind::UndeclaredArch(arch.to_owned()),
+                    None => ParseWarningKind::UnknownKey,
+                },
+            });
+        }
+
+        if let Some(first) = seen.get(entry.key.as_str()) {
+            warnings.push(ParseWarning {
+                line,
+                key: entry.key.clone(),
+                kind: ParseWarningKind::DuplicateKey(*first),
+            });
+        } else {
+            seen.insert(&entry.key, line);
+        }
+    }
+
+    warnings
+}
+
+/// Checks the values of the scanned entries that can not be checked while mapping them.
+///
+/// Only the entry that wins for each key is checked
+/// # Arguments
+/// * `entries` - The entries to check
+fn check_values(entries: &[Entry]) -> Vec<ParseWarning> {
+    let mut warnings: Vec<ParseWarning> = Vec::new();
+
+    for key in ["real_version", "epoch", "strip"] {
+        let entry = match entries
+            .iter()
+            .rev()
+            .find(|e| e.key == key && e.value.is_some())
+        {
+            Some(entry) => entry,
+            None => continue,
+        };
+
+        // Arrays are rejected when mapping the entries
+        let value = match &entry.value {
+            Some(ParseResult::String(v)) => v,
+            _ => continue,
+        };
+
+        let problem = match key {
+            "strip" if !matches!(value.as_str(), "1" | "true" | "0" | "false") => {
+                Some(format!("'{value}' is not a valid value, expected 1 or 0"))
+            }
+            "strip" => None,
+            _ => value
+                .parse::<u32>()
+                .err()
+                .map(|e| format!("'{value}' is not a valid number: {e}")),
+        };
+
+        if let Some(problem) = problem {
+            warnings.push(ParseWarning {
+                line: entry.lines.start + 1,
+                key: key.to_owned(),
+                kind: ParseWarningKind::InvalidValue(problem),
+            });
+        }
+    }
+
+    warnings
+}
+
+/// Logs the supplied warnings, invalid values are returned as an error instead
+/// # Arguments
+/// * `warnings` - The warnings to report
+fn report_warnings(warnings: Vec<ParseWarning>) -> Result<(), Error> {
+    for warning in warnings {
+        match warning.kind {
+            ParseWarningKind::InvalidValue(_) => {
+                return Err(Error::new(ErrorKind::InvalidData, warning.to_string()))
+            }
+            _ => warn!("{warning}"),
+        }
+    }
+
+    Ok(())
+}
+
+///Collects the scanned entries into a map, later entries override earlier ones
+///
+/// Empty values, arrays and blocks are ignored.
+/// # Arguments
+/// * `entries` - The scanned entries
+fn collect_entries(entries: Vec<Entry>) -> HashMap<String, ParseResult> {
+    let mut map: HashMap<String, ParseResult> = HashMap::new();
+
+    for entry in entries {
+        match entry.value {
+            None => {}
+            Some(ParseResult::Vec(v)) if v.is_empty() => {}
+            Some(value) => {
+                map.insert(entry.key, value);
             }
         }
     }
 
-    Ok(map)
+    map
 }
 
-/// Parses a packagebuild from the supplied input
+/// Parses a packagebuild from the supplied input.
+///
+/// Unknown and duplicate keys are logged as warnings, use `parse_with_mode()` to handle them.
+/// Values that do not fit their key, such as `strip=yes`, are errors.
 /// # Arguments
 /// * `input` - The packagebuild as an input implementing Read
 pub fn parse<R: Read>(input: &mut R) -> Result<PackageBuild, std::io::Error> {
+    let (pkgbuild, warnings) = parse_with_mode(input, ParseMode::Lenient)?;
+    report_warnings(warnings)?;
+
+    Ok(pkgbuild)
+}
+
+/// Parses the packagebuild file at the supplied path and remembers the directory
+/// it is in as its origin, so relative sources resolve against it.
+///
+/// Templates are not resolved, use `TemplateResolver::parse_file()` for packagebuilds that `inherit`
+/// # Arguments
+/// * `path` - The path of the packagebuild file
+pub fn parse_file(path: &Path) -> Result<PackageBuild, std::io::Error> {
+    read_file(path, parse)
+}
+
+/// Opens the packagebuild file at the supplied path, parses it using the supplied function
+/// and sets the origin of the packagebuild
+/// # Arguments
+/// * `path` - The path of the packagebuild file
+/// * `parse` - The function parsing the file
+fn read_file<F>(path: &Path, parse: F) -> Result<PackageBuild, Error>
+where
+    F: FnOnce(&mut File) -> Result<PackageBuild, Error>,
+{
+    let mut file = File::open(path).err_prepend(&format!(
+        "When opening packagebuild {}",
+        path.to_string_lossy()
+    ))?;
+
+    let mut pkgbuild = parse(&mut file).err_prepend(&format!(
+        "When parsing packagebuild {}",
+        path.to_string_lossy()
+    ))?;
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    pkgbuild.origin = Some(fs::canonicalize(dir).err_prepend(&format!(
+        "When resolving directory of packagebuild {}",
+        path.to_string_lossy()
+    ))?);
+
+    Ok(pkgbuild)
+}
+
+impl PackageBuild {
+    /// Loads the packagebuild bundle in the supplied directory: The only `.bpb` file in it
+    /// together with the patches, configuration files and others its relative sources refer to.
+    ///
+    /// Templates are not resolved, use `TemplateResolver::load_dir()` for packagebuilds that `inherit`
+    /// # Arguments
+    /// * `dir` - The directory of the bundle
+    pub fn load_dir(dir: &Path) -> Result<PackageBuild, std::io::Error> {
+        parse_file(&find_bundle_file(dir)?)
+    }
+}
+
+/// Returns the path of the only `.bpb` file in the supplied bundle directory
+/// # Arguments
+/// * `dir` - The directory of the bundle
+fn find_bundle_file(dir: &Path) -> Result<PathBuf, Error> {
+    let mut pkgbuilds = Vec::new();
+    for entry in
+        fs::read_dir(dir).err_prepend(&format!("When reading bundle {}", dir.to_string_lossy()))?
+    {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|e| e == "bpb") {
+            pkgbuilds.push(path);
+        }
+    }
+
+    match pkgbuilds.as_slice() {
+        [path] => Ok(path.clone()),
+        [] => Err(Error::new(
+            ErrorKind::NotFound,
+            format!("No packagebuild in bundle {}", dir.to_string_lossy()),
+        )),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Expected one packagebuild in bundle {}, found {}",
+                dir.to_string_lossy(),
+                pkgbuilds.len()
+            ),
+        )),
+    }
+}
+
+/// Parses a packagebuild from the supplied input using the supplied mode.
+///
+/// In lenient mode, unknown and duplicate keys and values that do not fit their key
+/// are returned as warnings, leaving the fields of invalid values unset or 0.
+/// In strict mode, they are errors.
+/// # Arguments
+/// * `input` - The packagebuild as an input implementing Read
+/// * `mode` - The mode to parse in
+pub fn parse_with_mode<R: Read>(
+    input: &mut R,
+    mode: ParseMode,
+) -> Result<(PackageBuild, Vec<ParseWarning>), std::io::Error> {
+    let (entries, warnings) = read_entries(input, mode)?;
+
+    Ok((map_pkgbuild(&entries)?, warnings))
+}
+
+/// Reads and scans the supplied input into a map of entries, checking the keys and values
+/// # Arguments
+/// * `input` - The packagebuild as an input implementing Read
+/// * `mode` - The mode to parse in
+fn read_entries<R: Read>(
+    input: &mut R,
+    mode: ParseMode,
+) -> Result<(HashMap<String, ParseResult>, Vec<ParseWarning>), Error> {
     // Get all the contents
     let mut contents = String::new();
     input.read_to_string(&mut contents)?;
 
-    // Get all the lines and parse the packagebuild
+    // Get all the lines and scan the packagebuild
     let lines: Vec<&str> = contents.split('\n').collect();
-    let entries = parse_pkgbuild(lines)?;
+    let scanned = scan_pkgbuild(&lines)?;
+
+    let mut warnings = check_keys(&scanned);
+    warnings.extend(check_values(&scanned));
+    if mode == ParseMode::Strict && !warnings.is_empty() {
+        let messages: Vec<String> = warnings.iter().map(|w| w.to_string()).collect();
+        return Err(Error::new(ErrorKind::InvalidData, messages.join(", ")));
+    }
+
+    Ok((collect_entries(scanned), warnings))
+}
+
+/// Maps the collected entries to a packagebuild
+/// # Arguments
+/// * `entries` - The entries to map
+fn map_pkgbuild(entries: &HashMap<String, ParseResult>) -> Result<PackageBuild, Error> {
+    // Invalid values have already been reported by `check_values()`
 
     // Parse `real_version` into a u32
-    let real_version: u32 = match entries.get_str("real_version")?.parse() {
-        Ok(v) => v,
-        Err(e) => {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                format!("Parsing real_version failed: {e}"),
-            ))
-        }
-    };
+    let real_version: u32 = entries.get_str("real_version")?.parse().unwrap_or_default();
+
+    // Parse `epoch` into a u32
+    let epoch: Option<u32> = entries.get_str_opt("epoch")?.and_then(|e| e.parse().ok());
 
     // Parse `strip` into a boolean
-    let strip = entries.get_str_opt("strip")?.map(|s| s == "1");
+    let strip = match entries.get_str_opt("strip")?.as_deref() {
+        Some("1") | Some("true") => Some(true),
+        Some("0") | Some("false") => Some(false),
+        _ => None,
+    };
+
+    // Split off inline checksums of the sources
+    let (source, source_sha256) = source_checksum(
+        entries.get_str_opt("source")?,
+        entries.get_str_opt("source_sha256")?,
+    )?;
+    let (extra_sources, extra_sources_sha256) = extra_sources_checksums(
+        entries.get_vec_opt("extra_sources")?,
+        entries.get_vec_opt("extra_sources_sha256")?,
+    )?;
 
     //Map the PackageBuild struct
     Ok(PackageBuild {
         name: entries.get_str("name")?,
         version: entries.get_str("version")?,
         real_version,
+        epoch,
+        inherit: entries.get_str_opt("inherit")?,
 
         maintainer: entries.get_str_opt("maintainer")?,
         maintainer_email: entries.get_str_opt("maintainer_email")?,
         description: entries.get_str_opt("description")?,
         provides: entries.get_vec_opt("provides")?,
-        source: entries.get_str_opt("source")?,
-        extra_sources: entries.get_vec_opt("extra_sources")?,
-        extra_dependencies: entries.get_vec_opt("extra_dependencies")?,
-        optional_dependencies: entries.get_vec_opt("optional_dependencies")?,
-        build_dependencies: entries.get_vec_opt("build_dependencies")?,
-        cross_dependencies: entries.get_vec_opt("cross_dependencies")?,
+        arch: entries.get_vec_opt("arch")?,
+        source,
+        source_sha256,
+        extra_sources,
+        extra_sources_sha256,
+        extra_dependencies: parse_dependencies(entries.get_vec_opt("extra_dependencies")?)?,
+        optional_dependencies: parse_dependencies(entries.get_vec_opt("optional_dependencies")?)?,
+        build_dependencies: parse_dependencies(entries.get_vec_opt("build_dependencies")?)?,
+        cross_dependencies: parse_dependencies(entries.get_vec_opt("cross_dependencies")?)?,
         preinstall: entries.get_str_opt("preinstall")?,
         postinstall: entries.get_str_opt("postinstall")?,
         strip,
+        build_system: entries
+            .get_str_opt("build_system")?
+            .map(|s| s.parse())
+            .transpose()?,
+        split_packages: entries
+            .get_vec_opt("split_packages")?
+            .map(|names| {
+                names
+                    .iter()
+                    .map(|name| map_split_package(entries, name))
+                    .collect()
+            })
+            .transpose()?,
+        arch_overrides: map_arch_overrides(entries)?,
+        vars: parse_variables(entries.get_vec_opt("vars")?)?,
 
         prepare: entries.get_vec_opt("prepare")?,
         build: entries.get_vec_opt("build")?,
         check: entries.get_vec_opt("check")?,
         package: entries.get_vec_opt("package")?,
+
+        origin: None,
+    })
+}
+
+/// Maps the `<key>_<name>` entries of a split package to a split package
+/// # Arguments
+/// * `entries` - The entries to map
+/// * `name` - The name of the split package
+fn map_split_package(
+    entries: &HashMap<String, ParseResult>,
+    name: &str,
+) -> Result<SplitPackage, Error> {
+    // The name ends up in paths of the output directories
+    check_name(name).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid split package name: {e}"),
+        )
+    })?;
+
+    let key = |key: &str| format!("{key}_{name}");
+
+    Ok(SplitPackage {
+        name: name.to_owned(),
+        description: entries.get_str_opt(&key("description"))?,
+        provides: entries.get_vec_opt(&key("provides"))?,
+        extra_dependencies: parse_dependencies(entries.get_vec_opt(&key("extra_dependencies"))?)?,
+        optional_dependencies: parse_dependencies(
+            entries.get_vec_opt(&key("optional_dependencies"))?,
+        )?,
+        files: entries.get_vec_opt(&key("files"))?,
+        package: entries.get_vec_opt(&key("package"))?,
     })
 }
+
+/// Maps the `<key>_<arch>` entries of all the declared architectures that override
+/// at least one key
+/// # Arguments
+/// * `entries` - The entries to map
+fn map_arch_overrides(
+    entries: &HashMap<String, ParseResult>,
+) -> Result<Option<Vec<ArchOverride>>, Error> {
+    let mut overrides: Vec<ArchOverride> = Vec::new();
+
+    for arch in entries.get_vec_opt("arch")?.iter().flatten() {
+        let key = |key: &str| format!("{key}_{arch}");
+        if !ARCH_KEYS.iter().any(|k| entries.contains_key(&key(k))) {
+            continue;
+        }
+
+        let (source, source_sha256) = source_checksum(
+            entries.get_str_opt(&key("source"))?,
+            entries.get_str_opt(&key("source_sha256"))?,
+        )?;
+
+        overrides.push(ArchOverride {
+            arch: arch.clone(),
+            source,
+            source_sha256,
+            extra_dependencies: parse_dependencies(
+                entries.get_vec_opt(&key("extra_dependencies"))?,
+            )?,
+            optional_dependencies: parse_dependencies(
+                entries.get_vec_opt(&key("optional_dependencies"))?,
+            )?,
+            build_dependencies: parse_dependencies(
+                entries.get_vec_opt(&key("build_dependencies"))?,
+            )?,
+            cross_dependencies: parse_dependencies(
+                entries.get_vec_opt(&key("cross_dependencies"))?,
+            )?,
+
+            prepare: entries.get_vec_opt(&key("prepare"))?,
+            build: entries.get_vec_opt(&key("build"))?,
+            check: entries.get_vec_opt(&key("check"))?,
+            package: entries.get_vec_opt(&key("package"))?,
+        });
+    }
+
+    Ok(if overrides.is_empty() {
+        None
+    } else {
+        Some(overrides)
+    })
+}
+
+/// A source field and the checksums of its sources
+type WithChecksums<T> = (Option<T>, Option<T>);
+
+/// Splits an inline checksum off a source and merges it with the explicit one
+/// # Arguments
+/// * `source` - The source, possibly with an inline checksum
+/// * `checksum` - The explicit checksum
+fn source_checksum(
+    source: Option<String>,
+    checksum: Option<String>,
+) -> Result<WithChecksums<String>, Error> {
+    let source = match source {
+        Some(source) => source,
+        None => return Ok((None, checksum)),
+    };
+
+    let (url, inline) = split_inline_checksum(&source)?;
+    let checksum = match (inline, checksum) {
+        (Some(inline), Some(explicit)) if !inline.eq_ignore_ascii_case(&explicit) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Conflicting checksums for source '{url}': '{inline}' and '{explicit}'"),
+            ))
+        }
+        (Some(inline), _) => Some(inline),
+        (None, explicit) => explicit,
+    };
+
+    Ok((Some(url), checksum))
+}
+
+/// Splits the inline checksums off the extra sources and merges them with the explicit ones,
+/// sources without a checksum get `SKIP`
+/// # Arguments
+/// * `sources` - The extra sources, possibly with inline checksums
+/// * `checksums` - The explicit checksums, one for every extra source
+fn extra_sources_checksums(
+    sources: Option<Vec<String>>,
+    checksums: Option<Vec<String>>,
+) -> Result<WithChecksums<Vec<String>>, Error> {
+    let sources = match sources {
+        Some(sources) => sources,
+        None => return Ok((None, checksums)),
+    };
+
+    if let Some(checksums) = &checksums {
+        if checksums.len() != sources.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Got {} checksums for {} extra sources",
+                    checksums.len(),
+                    sources.len()
+                ),
+            ));
+        }
+    }
+
+    let mut urls: Vec<String> = Vec::new();
+    let mut merged: Vec<String> = Vec::new();
+    for (i, source) in sources.into_iter().enumerate() {
+        let explicit = checksums
+            .as_ref()
+            .map(|c| c[i].clone())
+            .filter(|c| c != CHECKSUM_SKIP);
+
+        let (url, checksum) = source_checksum(Some(source), explicit)?;
+        urls.extend(url);
+        merged.push(checksum.unwrap_or_else(|| CHECKSUM_SKIP.to_owned()));
+    }
+
+    let merged = if merged.iter().all(|c| c == CHECKSUM_SKIP) {
+        None
+    } else {
+        Some(merged)
+    };
+
+    Ok((Some(urls), merged))
+}
+
+/// Parses the items of a dependency array into dependencies
+/// # Arguments
+/// * `items` - The items to parse
+fn parse_dependencies(items: Option<Vec<String>>) -> Result<Option<Vec<Dependency>>, Error> {
+    items
+        .map(|items| items.iter().map(|i| i.parse()).collect())
+        .transpose()
+}
+
+/// Parses the lines of a variables block into variables
+/// # Arguments
+/// * `lines` - The lines to parse
+fn parse_variables(lines: Option<Vec<String>>) -> Result<Option<Vec<Variable>>, Error> {
+    // Variables are separated by newlines or `;`: `vars={ a=1 ; b=2 }`
+    lines
+        .map(|lines| {
+            lines
+                .iter()
+                .flat_map(|l| l.split(';'))
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::parse)
+                .collect()
+        })
+        .transpose()
+}
+
+impl Display for ParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ParseWarningKind::UnknownKey => {
+                write!(f, "Line {}: Unknown key '{}'", self.line, self.key)
+            }
+            ParseWarningKind::DuplicateKey(first) => write!(
+                f,
+                "Line {}: Duplicate key '{}', first defined on line {}",
+                self.line, self.key, first
+            ),
+            ParseWarningKind::UndeclaredArch(arch) => {
+                write!(
+                    f,
+                    "Line {}: Key '{}' overrides the architecture '{arch}', which is not declared in 'arch'",
+                    self.line, self.key
+                )?;
+                if is_suffixed_key(&self.key, SPLIT_KEYS, std::slice::from_ref(arch)) {
+                    write!(f, ", nor is it a split package")?;
+                }
+                Ok(())
+            }
+            ParseWarningKind::InvalidValue(problem) => {
+                write!(f, "Line {}: Invalid {}: {}", self.line, self.key, problem)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(s: &str) -> Result<PackageBuild, Error> {
+        parse(&mut s.as_bytes())
+    }
+
+    #[test]
+    fn comments() {
+        let commented = include_str!("../doc/pkgbuild.bpb");
+        let uncommented: String = commented
+            .lines()
+            .filter(|l| !l.trim_start().starts_with('#'))
+            .map(|l| format!("{l}\n"))
+            .collect();
+        assert_ne!(commented, uncommented);
+        assert_eq!(
+            parse_str(commented).unwrap(),
+            parse_str(&uncommented).unwrap()
+        );
+
+        let trailing = uncommented
+            .replace("version=0.2.2", "version=0.2.2 # upstream=0.2.2")
+            .replace("provides=[leaf]", "provides=[leaf]# the binary")
+            .replace("strip=1", "strip=1\t# strip it")
+            .replace("}\n", "} # end of block\n");
+        assert_eq!(
+            parse_str(&trailing).unwrap(),
+            parse_str(&uncommented).unwrap()
+        );
+    }
+
+    #[test]
+    fn hashes_within_values() {
+        let pkgbuild = parse_str(
+            "name=foo\nversion=1.0\nreal_version=0\ndescription=A C# compiler\n\
+             source=https://example.org/foo.tar.gz#extract=0\n\
+             provides=[a #b][#c]\n\
+             build={\n  # configure\n  echo \"#\" # done\n}\n",
+        )
+        .unwrap();
+
+        assert_eq!(pkgbuild.description.as_deref(), Some("A C# compiler"));
+        assert_eq!(
+            pkgbuild.source.as_deref(),
+            Some("https://example.org/foo.tar.gz#extract=0")
+        );
+        assert_eq!(
+            pkgbuild.provides,
+            Some(vec!["a #b".to_owned(), "#c".to_owned()])
+        );
+        assert_eq!(
+            pkgbuild.build,
+            Some(vec![
+                "# configure".to_owned(),
+                "echo \"#\" # done".to_owned()
+            ])
+        );
+    }
+
+    fn parse_mode(s: &str, mode: ParseMode) -> Result<Vec<ParseWarning>, Error> {
+        parse_with_mode(&mut s.as_bytes(), mode).map(|(_, warnings)| warnings)
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let input = "name=foo\nversion=1.0\nreal_version=0\nmaintainr=Someone\n";
+
+        assert_eq!(
+            parse_mode(input, ParseMode::Lenient).unwrap(),
+            vec![ParseWarning {
+                line: 4,
+                key: "maintainr".to_owned(),
+                kind: ParseWarningKind::UnknownKey,
+            }]
+        );
+        let err = parse_mode(input, ParseMode::Strict).unwrap_err();
+        assert!(err.to_string().contains("Line 4: Unknown key 'maintainr'"));
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        let input = "name=foo\nversion=1.0\nreal_version=0\nversion=2.0\n";
+
+        assert_eq!(
+            parse_mode(input, ParseMode::Lenient).unwrap(),
+            vec![ParseWarning {
+                line: 4,
+                key: "version".to_owned(),
+                kind: ParseWarningKind::DuplicateKey(2),
+            }]
+        );
+        assert_eq!(parse_str(input).unwrap().version, "2.0");
+        assert!(parse_mode(input, ParseMode::Strict).is_err());
+    }
+
+    #[test]
+    fn suffixed_keys() {
+        let input = "name=foo\nversion=1.0\nreal_version=0\n\
+                     arch=[x86_64][aarch64]\nsplit_packages=[foo-dev]\n\
+                     build_dependencies_aarch64=[gcc]\nsource_x86_64=https://example.org/a\n\
+                     description_foo-dev=Headers\npackage_foo-dev={\n  true\n}\n";
+        assert_eq!(parse_mode(input, ParseMode::Strict).unwrap(), vec![]);
+
+        // Only the declared architectures and split packages are known
+        let input = "name=foo\nversion=1.0\nreal_version=0\narch=[x86_64]\n\
+                     build_riscv64={\n  true\n}\ndescription_foo-doc=Docs\n";
+        let warnings = parse_mode(input, ParseMode::Lenient).unwrap();
+        let keys: Vec<(&str, &ParseWarningKind)> =
+            warnings.iter().map(|w| (w.key.as_str(), &w.kind)).collect();
+        assert_eq!(
+            keys,
+            vec![
+                (
+                    "build_riscv64",
+                    &ParseWarningKind::UndeclaredArch("riscv64".to_owned())
+                ),
+                ("description_foo-doc", &ParseWarningKind::UnknownKey)
+            ]
+        );
+        assert!(parse_mode(input, ParseMode::Strict).is_err());
+
+        // Without `arch`, no overrides are known
+        let input = "name=foo\nversion=1.0\nreal_version=0\nsource_sha256_x86_64=SKIP\n";
+        assert_eq!(
+            parse_mode(input, ParseMode::Lenient).unwrap()[0].kind,
+            ParseWarningKind::UndeclaredArch("x86_64".to_owned())
+        );
+    }
+
+    #[test]
+    fn block_with_arithmetic_shift() {
+        let pkgbuild =
+            parse_str("name=foo\nversion=1.0\nreal_version=0\nbuild={ echo $((1<<2)) }\n").unwrap();
+        assert_eq!(pkgbuild.build, Some(vec!["echo $((1<<2))".to_owned()]));
+    }
+
+    #[test]
+    fn vars_separated_by_semicolons() {
+        let pkgbuild = parse_str(
+            "name=foo\nversion=1.0\nreal_version=0\nvars={ major=1.2 ; srcname=foo-$PKG_VERSION }\n",
+        )
+        .unwrap();
+
+        let vars = pkgbuild.vars.clone().unwrap();
+        assert_eq!(vars.len(), 2);
+        assert_eq!(
+            (vars[0].name.as_str(), vars[0].value.as_str()),
+            ("major", "1.2")
+        );
+        assert_eq!(
+            (vars[1].name.as_str(), vars[1].value.as_str()),
+            ("srcname", "foo-$PKG_VERSION")
+        );
+
+        let variables = pkgbuild.resolved_variables().unwrap();
+        assert_eq!(variables["major"], "1.2");
+        assert_eq!(variables["srcname"], "foo-1.0");
+    }
+
+    #[test]
+    fn vars_separated_by_lines() {
+        let pkgbuild =
+            parse_str("name=foo\nversion=1.0\nreal_version=0\nvars={\n  a=1\n\n  b=$a;\n}\n")
+                .unwrap();
+        assert_eq!(pkgbuild.resolved_variables().unwrap()["b"], "1");
+    }
+
+    #[test]
+    fn invalid_values() {
+        let input = "name=foo\nversion=1.0\nreal_version=-1\nstrip=yes\nepoch=x\n";
+        assert!(parse_str(input).is_err());
+        assert!(parse_str("name=foo\nversion=1.0\nreal_version=0\nstrip=yes\n").is_err());
+        assert!(parse_with_mode(&mut input.as_bytes(), ParseMode::Strict).is_err());
+
+        let (pkgbuild, warnings) =
+            parse_with_mode(&mut input.as_bytes(), ParseMode::Lenient).unwrap();
+        assert_eq!(pkgbuild.real_version, 0);
+        assert_eq!(pkgbuild.strip, None);
+        assert_eq!(pkgbuild.epoch, None);
+        let invalid: Vec<(usize, &str)> = warnings
+            .iter()
+            .filter(|w| matches!(w.kind, ParseWarningKind::InvalidValue(_)))
+            .map(|w| (w.line, w.key.as_str()))
+            .collect();
+        assert_eq!(
+            invalid,
+            vec![(3, "real_version"), (5, "epoch"), (4, "strip")]
+        );
+
+        // Only the definition that wins counts
+        assert!(parse_str("name=foo\nversion=1.0\nreal_version=x\nreal_version=1\n").is_ok());
+    }
+
+    #[test]
+    fn strip_values() {
+        let strip = |value: &str| {
+            parse_str(&format!(
+                "name=foo\nversion=1.0\nreal_version=0\nstrip={value}\n"
+            ))
+            .unwrap()
+            .strip
+        };
+        assert_eq!(strip("1"), Some(true));
+        assert_eq!(strip("true"), Some(true));
+        assert_eq!(strip("0"), Some(false));
+        assert_eq!(strip("false"), Some(false));
+    }
+
+    #[test]
+    fn split_packages() {
+        let pkgbuild = parse_str(
+            "name=foo\nversion=1.0\nreal_version=0\nsplit_packages=[foo-dev][foo-doc]\n\
+             description_foo-dev=Headers\nfiles_foo-dev=[usr/include/**]\n\
+             package_foo-doc={\n  mkdir -p $PKG_INSTALL_DIR/doc\n}\n",
+        )
+        .unwrap();
+
+        assert_eq!(pkgbuild.output_names(), vec!["foo", "foo-dev", "foo-doc"]);
+        let dev = pkgbuild.split_package("foo-dev").unwrap();
+        assert_eq!(dev.description.as_deref(), Some("Headers"));
+        assert_eq!(dev.files, Some(vec!["usr/include/**".to_owned()]));
+        assert_eq!(
+            pkgbuild.split_package("foo-doc").unwrap().package,
+            Some(vec!["mkdir -p $PKG_INSTALL_DIR/doc".to_owned()])
+        );
+        assert!(pkgbuild.split_package("foo").is_none());
+    }
+
+    #[test]
+    fn invalid_split_package_names() {
+        for name in ["../x", "a/b", "", "Foo", ".."] {
+            let res = parse_str(&format!(
+                "name=foo\nversion=1.0\nreal_version=0\nsplit_packages=[{name}]\n"
+            ));
+            assert!(res.is_err(), "accepted split package name '{name}'");
+        }
+    }
+
+    #[test]
+    fn epoch() {
+        let pkgbuild = parse_str("name=foo\nversion=1.0\nreal_version=0\nepoch=2\n").unwrap();
+        assert_eq!(pkgbuild.epoch, Some(2));
+        assert_eq!(parse_str(&pkgbuild.to_bpb().unwrap()).unwrap(), pkgbuild);
+
+        assert!(parse_str("name=foo\nversion=1.0\nreal_version=0\nepoch=x\n").is_err());
+    }
+}
@@ -0,0 +1,497 @@
+use super::util::{escape_array_item, split_comment};
+use super::writer::{check_block, check_str};
+use super::{scan_pkgbuild, Entry, ParseResult, KEYS};
+use crate::PackageBuild;
+use std::{
+    fmt::Display,
+    io::{Error, ErrorKind, Read},
+    ops::Range,
+    str::FromStr,
+};
+
+/// A lossless model of a packagebuild that keeps comments, blank lines, key order and indentation.
+///
+/// Edits only touch the lines of the affected entry, the rest of the document stays untouched.
+/// If a key is present multiple times, the last occurrence is the effective one.
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub(super) lines: Vec<String>,
+    pub(super) entries: Vec<Entry>,
+}
+
+impl Document {
+    /// Parses a document from the supplied input
+    /// # Arguments
+    /// * `input` - The packagebuild as an input implementing Read
+    pub fn parse<R: Read>(input: &mut R) -> Result<Self, Error> {
+        let mut contents = String::new();
+        input.read_to_string(&mut contents)?;
+
+        Self::from_lines(contents.split('\n').map(|l| l.to_string()).collect())
+    }
+
+    /// Creates a document from its lines, scanning them for entries
+    /// # Arguments
+    /// * `lines` - The lines of the document
+    pub(super) fn from_lines(lines: Vec<String>) -> Result<Self, Error> {
+        let refs: Vec<&str> = lines.iter().map(|l| l.as_str()).collect();
+        let entries = scan_pkgbuild(&refs)?;
+
+        Ok(Self { lines, entries })
+    }
+
+    /// Returns the keys of all entries in the order they appear in
+    pub fn keys(&self) -> Vec<&str> {
+        self.entries.iter().map(|e| e.key.as_str()).collect()
+    }
+
+    /// Parses the document into a packagebuild
+    pub fn to_pkgbuild(&self) -> Result<PackageBuild, Error> {
+        super::parse(&mut self.to_string().as_bytes())
+    }
+
+    /// Returns the string value for the supplied key, `None` if it is absent or empty.
+    ///
+    /// If the value is not a String, this returns Err(InvalidData).
+    /// # Arguments
+    /// * `key` - The key to search for
+    pub fn get_str(&self, key: &str) -> Result<Option<String>, Error> {
+        match self.entry(key).and_then(|e| e.value.as_ref()) {
+            None => Ok(None),
+            Some(ParseResult::String(v)) => Ok(Some(v.clone())),
+            Some(ParseResult::Vec(_)) => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected String, got Vec for key '{key}'"),
+            )),
+        }
+    }
+
+    /// Returns the array or block value for the supplied key, `None` if it is absent or empty.
+    ///
+    /// If the value is not a Vec, this returns Err(InvalidData).
+    /// # Arguments
+    /// * `key` - The key to search for
+    pub fn get_vec(&self, key: &str) -> Result<Option<Vec<String>>, Error> {
+        match self.entry(key).and_then(|e| e.value.as_ref()) {
+            None => Ok(None),
+            Some(ParseResult::Vec(v)) => Ok(Some(v.clone())),
+            Some(ParseResult::String(_)) => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Expected Vec, got String for key '{key}'"),
+            )),
+        }
+    }
+
+    /// Sets the string value for the supplied key.
+    ///
+    /// An existing entry keeps its indentation and spacing around `=`,
+    /// a new entry gets inserted at its canonical position.
+    /// # Arguments
+    /// * `key` - The key to set
+    /// * `value` - The new value
+    pub fn set_str(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        check_str(key, value)?;
+
+        match self.entry(key) {
+            Some(entry) => {
+                let line = with_comment(
+                    format!("{}{}", self.prefix(entry), value),
+                    self.comment(entry),
+                );
+                self.splice(entry.lines.clone(), vec![line])
+            }
+            None => {
+                let pos = self.insertion_point(key);
+                self.splice(pos..pos, vec![format!("{key}={value}")])
+            }
+        }
+    }
+
+    /// Appends an item to the array for the supplied key, creating it if necessary
+    /// # Arguments
+    /// * `key` - The key of the array
+    /// * `item` - The item to append
+    pub fn push_array(&mut self, key: &str, item: &str) -> Result<(), Error> {
+        let item = format!("[{}]", escape_array_item(item));
+
+        match self.entry(key) {
+            Some(entry) => {
+                let value = self.raw_value(entry);
+                if !value.is_empty() && !value.starts_with('[') {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Entry '{key}' is not an array"),
+                    ));
+                }
+
+                let line = with_comment(
+                    format!("{}{}{}", self.prefix(entry), value, item),
+                    self.comment(entry),
+                );
+                self.splice(entry.lines.clone(), vec![line])
+            }
+            None => {
+                let pos = self.insertion_point(key);
+                self.splice(pos..pos, vec![format!("{key}={item}")])
+            }
+        }
+    }
+
+    /// Replaces the lines of the block for the supplied key, creating it if necessary.
+    ///
+    /// An existing block keeps the indentation of its lines and its closing brace.
+    /// # Arguments
+    /// * `key` - The key of the block
+    /// * `lines` - The new lines of the block
+    pub fn replace_block(&mut self, key: &str, lines: &[String]) -> Result<(), Error> {
+        check_block(key, lines)?;
+
+        match self.entry(key) {
+            Some(entry) => {
+                let value = self.raw_value(entry);
+                if !value.is_empty() && !value.starts_with('{') {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Entry '{key}' is not a block"),
+                    ));
+                }
+
+                // Take the indentation from the old block
+                let body = &self.lines[entry.lines.start + 1..entry.lines.end];
+                let indent = body
+                    .iter()
+                    .take(body.len().saturating_sub(1))
+                    .find(|l| !l.trim().is_empty())
+                    .map(|l| leading_whitespace(l))
+                    .unwrap_or("    ");
+                let closing = match body.last() {
+                    Some(l) if l.trim() == "}" => l.trim_end().to_string(),
+                    _ => "}".to_string(),
+                };
+
+                let mut new_lines = vec![format!("{}{{", self.prefix(entry))];
+                new_lines.extend(lines.iter().map(|l| format!("{indent}{l}")));
+                new_lines.push(closing);
+
+                self.splice(entry.lines.clone(), new_lines)
+            }
+            None => {
+                let pos = self.insertion_point(key);
+
+                let mut new_lines = Vec::new();
+                if pos > 0 && !self.lines[pos - 1].trim().is_empty() {
+                    new_lines.push(String::new());
+                }
+                new_lines.push(format!("{key}={{"));
+                new_lines.extend(lines.iter().map(|l| format!("    {l}")));
+                new_lines.push("}".to_string());
+
+                self.splice(pos..pos, new_lines)
+            }
+        }
+    }
+
+    /// Removes all entries with the supplied key, returns whether an entry was removed
+    /// # Arguments
+    /// * `key` - The key to remove
+    pub fn remove(&mut self, key: &str) -> Result<bool, Error> {
+        let ranges: Vec<Range<usize>> = self
+            .entries
+            .iter()
+            .filter(|e| e.key == key)
+            .map(|e| e.lines.clone())
+            .collect();
+
+        // Remove from the back to keep the other ranges valid
+        for range in ranges.iter().rev() {
+            self.lines.drain(range.clone());
+        }
+
+        self.rescan()?;
+        Ok(!ranges.is_empty())
+    }
+
+    /// Returns the effective (last) entry for the supplied key
+    /// # Arguments
+    /// * `key` - The key to search for
+    fn entry(&self, key: &str) -> Option<&Entry> {
+        self.entries.iter().rev().find(|e| e.key == key)
+    }
+
+    /// Returns the raw, trimmed value in the first line of an entry without its trailing comment
+    /// # Arguments
+    /// * `entry` - The entry to get the value of
+    pub(super) fn raw_value(&self, entry: &Entry) -> &str {
+        self.split_value(entry).0
+    }
+
+    /// Returns the trailing comment in the first line of an entry, blocks have none
+    /// # Arguments
+    /// * `entry` - The entry to get the comment of
+    pub(super) fn comment(&self, entry: &Entry) -> Option<&str> {
+        self.split_value(entry).1
+    }
+
+    /// Splits the first line of an entry after the `=` into its trimmed value and its comment
+    /// # Arguments
+    /// * `entry` - The entry to split
+    fn split_value(&self, entry: &Entry) -> (&str, Option<&str>) {
+        let rest = match self.lines[entry.lines.start].split_once('=') {
+            Some((_, rest)) => rest,
+            None => return ("", None),
+        };
+
+        if rest.trim_start().starts_with('{') {
+            return (rest.trim(), None);
+        }
+
+        let (value, comment) = split_comment(rest);
+        (value.trim(), comment.map(str::trim_end))
+    }
+
+    /// Returns the first line of an entry up to its value, keeping the spacing around `=`
+    /// # Arguments
+    /// * `entry` - The entry to get the prefix of
+    fn prefix(&self, entry: &Entry) -> String {
+        let line = &self.lines[entry.lines.start];
+        match line.split_once('=') {
+            Some((key, rest)) => format!("{key}={}", leading_whitespace(rest)),
+            None => format!("{}=", entry.key),
+        }
+    }
+
+    /// Finds the line to insert a new entry at, keeping the canonical key order if possible
+    /// # Arguments
+    /// * `key` - The key of the new entry
+    fn insertion_point(&self, key: &str) -> usize {
+        // Do not insert behind the final empty line of a trailing newline
+        let end = match self.lines.last() {
+            Some(l) if l.is_empty() => self.lines.len() - 1,
+            _ => self.lines.len(),
+        };
+
+        let rank = match KEYS.iter().position(|k| *k == key) {
+            Some(rank) => rank,
+            None => return self.entries.last().map(|e| e.lines.end).unwrap_or(end),
+        };
+
+        let preceding = self.entries.iter().rev().find(|e| {
+            KEYS.iter()
+                .position(|k| *k == e.key)
+                .is_some_and(|r| r <= rank)
+        });
+
+        match (preceding, self.entries.first()) {
+            (Some(e), _) => e.lines.end,
+            (None, Some(first)) => first.lines.start,
+            (None, None) => end,
+        }
+    }
+
+    /// Replaces a range of lines and rescans the document
+    /// # Arguments
+    /// * `range` - The range of lines to replace
+    /// * `lines` - The lines to insert instead
+    fn splice(&mut self, range: Range<usize>, lines: Vec<String>) -> Result<(), Error> {
+        self.lines.splice(range, lines);
+        self.rescan()
+    }
+
+    /// Scans the lines for entries again after modifications
+    fn rescan(&mut self) -> Result<(), Error> {
+        let refs: Vec<&str> = self.lines.iter().map(|l| l.as_str()).collect();
+        self.entries = scan_pkgbuild(&refs)?;
+        Ok(())
+    }
+}
+
+/// Appends the trailing comment of an entry to its new first line, if it has one
+/// # Arguments
+/// * `line` - The new first line of the entry
+/// * `comment` - The trailing comment of the entry
+pub(super) fn with_comment(line: String, comment: Option<&str>) -> String {
+    match comment {
+        Some(comment) => format!("{line} {comment}"),
+        None => line,
+    }
+}
+
+/// Returns the leading whitespace of the supplied string
+/// # Arguments
+/// * `s` - The string to inspect
+pub(super) fn leading_whitespace(s: &str) -> &str {
+    &s[..s.len() - s.trim_start().len()]
+}
+
+impl Display for Document {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.lines.join("\n"))
+    }
+}
+
+impl FromStr for Document {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_lines(s.split('\n').map(|l| l.to_string()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &str = "\
+# The foo package
+name = foo
+version=1.0
+real_version=0
+
+maintainer=Someone # not me
+provides=[foo] # binaries
+
+build={
+  make
+}
+";
+
+    fn document() -> Document {
+        DOCUMENT.parse().unwrap()
+    }
+
+    #[test]
+    fn lossless() {
+        let document = document();
+        assert_eq!(document.to_string(), DOCUMENT);
+        assert_eq!(
+            document.keys(),
+            vec![
+                "name",
+                "version",
+                "real_version",
+                "maintainer",
+                "provides",
+                "build"
+            ]
+        );
+        assert_eq!(document.get_str("name").unwrap().as_deref(), Some("foo"));
+        assert_eq!(
+            document.get_str("maintainer").unwrap().as_deref(),
+            Some("Someone")
+        );
+        assert_eq!(
+            document.get_vec("build").unwrap(),
+            Some(vec!["make".to_owned()])
+        );
+        assert!(document.get_vec("name").is_err());
+        assert!(document.get_str("provides").is_err());
+        assert_eq!(document.get_str("description").unwrap(), None);
+    }
+
+    #[test]
+    fn set_str() {
+        let mut document = document();
+        document.set_str("name", "bar").unwrap();
+        document.set_str("maintainer", "Someone else").unwrap();
+        document.set_str("description", "The bar package").unwrap();
+
+        assert_eq!(
+            document.to_string(),
+            DOCUMENT
+                .replace("name = foo", "name = bar")
+                .replace("Someone #", "Someone else #")
+                .replace("provides=", "description=The bar package\nprovides=")
+        );
+        assert!(document.set_str("name", "a\nb").is_err());
+        assert!(document.set_str("name", "a # b").is_err());
+    }
+
+    #[test]
+    fn push_array() {
+        let mut document = document();
+        document.push_array("provides", "foo[bin]").unwrap();
+        document.push_array("arch", "x86_64").unwrap();
+
+        assert_eq!(
+            document.to_string(),
+            DOCUMENT.replace(
+                "provides=[foo] # binaries",
+                "provides=[foo][foo\\[bin\\]] # binaries\narch=[x86_64]"
+            )
+        );
+        assert_eq!(
+            document.get_vec("provides").unwrap(),
+            Some(vec!["foo".to_owned(), "foo[bin]".to_owned()])
+        );
+        assert!(document.push_array("name", "x").is_err());
+    }
+
+    #[test]
+    fn replace_block() {
+        let mut document = document();
+        let lines = vec!["./configure".to_owned(), "make".to_owned()];
+        document.replace_block("build", &lines).unwrap();
+        document
+            .replace_block("check", &["make check".to_owned()])
+            .unwrap();
+
+        assert_eq!(
+            document.to_string(),
+            DOCUMENT.replace(
+                "build={\n  make\n}\n",
+                "build={\n  ./configure\n  make\n}\n\ncheck={\n    make check\n}\n"
+            )
+        );
+        assert!(document.replace_block("name", &lines).is_err());
+        assert!(document
+            .replace_block("build", &["echo }".to_owned()])
+            .is_err());
+    }
+
+    #[test]
+    fn replace_one_line_block() {
+        let mut document: Document = "name=foo\nbuild={ make }\nversion=1.0\n".parse().unwrap();
+        document
+            .replace_block("build", &["make install".to_owned()])
+            .unwrap();
+
+        assert_eq!(
+            document.to_string(),
+            "name=foo\nbuild={\n    make install\n}\nversion=1.0\n"
+        );
+        assert_eq!(document.get_str("version").unwrap().as_deref(), Some("1.0"));
+    }
+
+    #[test]
+    fn remove() {
+        let mut document: Document = format!("{DOCUMENT}build={{ true }}\n").parse().unwrap();
+        assert!(document.remove("build").unwrap());
+        assert!(!document.remove("build").unwrap());
+        assert!(document.remove("maintainer").unwrap());
+
+        assert_eq!(
+            document.to_string(),
+            DOCUMENT
+                .replace("maintainer=Someone # not me\n", "")
+                .replace("build={\n  make\n}\n", "")
+        );
+    }
+
+    #[test]
+    fn insertion_order() {
+        let mut document: Document = "# Header\nversion=1.0\nunknown=1\n".parse().unwrap();
+        document.set_str("name", "foo").unwrap();
+        document.set_str("other", "2").unwrap();
+        document.set_str("description", "Foo").unwrap();
+
+        // Before the first entry, after its canonical predecessor and unknown keys last
+        assert_eq!(
+            document.to_string(),
+            "# Header\nname=foo\nversion=1.0\ndescription=Foo\nunknown=1\nother=2\n"
+        );
+
+        let mut document: Document = "".parse().unwrap();
+        document.set_str("name", "foo").unwrap();
+        assert_eq!(document.to_string(), "name=foo\n");
+    }
+}
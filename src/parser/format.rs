@@ -0,0 +1,251 @@
+use super::document::{leading_whitespace, with_comment, Document};
+use super::multiline::BlockScanner;
+use super::util::serialize_array;
+use super::{Entry, ParseResult, KEYS};
+use std::io::{Error, Read, Write};
+
+/// The keys of arrays whose order does not matter and that get sorted when formatting
+const SORTED_KEYS: &[&str] = &[
+    "provides",
+    "extra_dependencies",
+    "optional_dependencies",
+    "build_dependencies",
+    "cross_dependencies",
+];
+
+/// An entry of the formatted document together with the comments preceding it
+struct Chunk {
+    rank: usize,
+    group: usize,
+    lines: Vec<String>,
+}
+
+/// Formats a packagebuild from the supplied input to its canonical form, see `Document::format()`
+/// # Arguments
+/// * `input` - The packagebuild as an input implementing Read
+/// * `output` - The output to write the formatted packagebuild to
+pub fn format<R: Read, W: Write>(input: &mut R, output: &mut W) -> Result<(), Error> {
+    let document = Document::parse(input)?;
+    write!(output, "{}", document.format()?)
+}
+
+impl Document {
+    /// Returns this document in its canonical form.
+    ///
+    /// The entries are ordered as in the spec, unknown keys follow in their original order.
+    /// There is no spacing around `=`, blocks are indented by 4 spaces (keeping relative
+    /// indentation) and arrays whose order does not matter are sorted.
+    /// Comments stay attached to the entry following them.
+    pub fn format(&self) -> Result<Document, Error> {
+        let mut chunks: Vec<Chunk> = Vec::new();
+        let mut comments: Vec<String> = Vec::new();
+        let mut cursor = 0;
+
+        for entry in &self.entries {
+            comments.extend(trivia(&self.lines[cursor..entry.lines.start]));
+            cursor = entry.lines.end;
+
+            let rank = KEYS
+                .iter()
+                .position(|k| *k == entry.key)
+                .unwrap_or(KEYS.len());
+            let is_block = self.raw_value(entry).starts_with('{');
+            let group = match rank {
+                0..=3 => 0,
+                _ if is_block => 2 + chunks.len(),
+                _ if rank < KEYS.len() => 1,
+                _ => usize::MAX,
+            };
+
+            let mut lines = std::mem::take(&mut comments);
+            if is_block {
+                lines.extend(self.format_block(entry));
+            } else {
+                lines.push(self.format_value(entry));
+            }
+
+            chunks.push(Chunk { rank, group, lines });
+        }
+        comments.extend(trivia(&self.lines[cursor..]));
+
+        chunks.sort_by_key(|c| c.rank);
+
+        let mut lines: Vec<String> = Vec::new();
+        let mut last_group: Option<usize> = None;
+        for chunk in chunks {
+            if last_group.is_some_and(|g| g != chunk.group) {
+                lines.push(String::new());
+            }
+            last_group = Some(chunk.group);
+            lines.extend(chunk.lines);
+        }
+
+        if !comments.is_empty() {
+            if !lines.is_empty() {
+                lines.push(String::new());
+            }
+            lines.extend(comments);
+        }
+
+        // End with a trailing newline
+        lines.push(String::new());
+
+        Document::from_lines(lines)
+    }
+
+    /// Formats a `key=value` or `key=[a][b]` entry, keeping its trailing comment
+    /// # Arguments
+    /// * `entry` - The entry to format
+    fn format_value(&self, entry: &Entry) -> String {
+        let line = match &entry.value {
+            None => format!("{}=", entry.key),
+            Some(ParseResult::String(v)) => format!("{}={}", entry.key, v),
+            Some(ParseResult::Vec(v)) => {
+                let mut items = v.clone();
+                if SORTED_KEYS.contains(&entry.key.as_str()) {
+                    items.sort();
+                }
+                format!("{}={}", entry.key, serialize_array(&items))
+            }
+        };
+
+        with_comment(line, self.comment(entry))
+    }
+
+    /// Formats a `key={}` block entry.
+    ///
+    /// If the block is in the usual form (braces on their own lines), the relative
+    /// indentation of its lines is kept, else every line is indented uniformly.
+    /// Heredocs are kept as they are.
+    /// # Arguments
+    /// * `entry` - The entry to format
+    fn format_block(&self, entry: &Entry) -> Vec<String> {
+        let parsed = match &entry.value {
+            Some(ParseResult::Vec(v)) => v.clone(),
+            _ => Vec::new(),
+        };
+
+        let mut res = vec![format!("{}={{", entry.key)];
+
+        let body = &self.lines[entry.lines.start + 1..entry.lines.end];
+        let usual_form = self.raw_value(entry) == "{"
+            && body.last().is_some_and(|l| l.trim() == "}")
+            && body[..body.len() - 1]
+                .iter()
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty())
+                .eq(parsed.iter().map(|l| l.as_str()));
+
+        if usual_form {
+            let body = &body[..body.len() - 1];
+
+            // The lines of heredocs are content, they keep their indentation and blank lines
+            let mut scanner = BlockScanner::new();
+            scanner.scan_line("{");
+            let heredoc: Vec<bool> = body
+                .iter()
+                .map(|l| {
+                    let in_heredoc = scanner.in_heredoc();
+                    scanner.scan_line(l);
+                    in_heredoc
+                })
+                .collect();
+
+            let min_indent = body
+                .iter()
+                .zip(&heredoc)
+                .filter(|(l, heredoc)| !**heredoc && !l.trim().is_empty())
+                .map(|(l, _)| leading_whitespace(l).chars().count())
+                .min()
+                .unwrap_or(0);
+
+            for (line, heredoc) in body.iter().zip(heredoc) {
+                if heredoc {
+                    res.push(line.clone());
+                } else if !line.trim().is_empty() {
+                    let line: String = line.chars().skip(min_indent).collect();
+                    res.push(format!("    {}", line.trim_end()));
+                }
+            }
+        } else {
+            res.extend(parsed.iter().map(|l| format!("    {l}")));
+        }
+
+        res.push("}".to_string());
+        res
+    }
+}
+
+/// Returns the comments and other non-empty lines between entries, trimmed
+/// # Arguments
+/// * `lines` - The lines between two entries
+fn trivia(lines: &[String]) -> Vec<String> {
+    lines
+        .iter()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .map(|l| l.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format_str(s: &str) -> String {
+        let mut output = Vec::new();
+        format(&mut s.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn canonical_form() {
+        let formatted = format_str(
+            "build={\n\tmake\n\t  make install\n}\n\
+             # Needed to build\n\
+             build_dependencies = [ninja][cmake>=3.20] # sorted\n\
+             custom=1\n\
+             version = 1.0\n\
+             name=foo\n\
+             prepare={ ./configure }\n\
+             real_version=0\n\
+             extra_sources=[b][a]\n",
+        );
+
+        assert_eq!(
+            formatted,
+            "name=foo\nversion=1.0\nreal_version=0\n\n\
+             extra_sources=[b][a]\n\
+             # Needed to build\n\
+             build_dependencies=[cmake>=3.20][ninja] # sorted\n\n\
+             prepare={\n    ./configure\n}\n\n\
+             build={\n    make\n      make install\n}\n\n\
+             custom=1\n"
+        );
+    }
+
+    #[test]
+    fn heredocs() {
+        let formatted = format_str(
+            "name=foo\nversion=1.0\nreal_version=0\n\
+             build={\n  cat <<EOF > foo.conf\n  indented\n\nEOF\n  make\n}\n",
+        );
+
+        assert_eq!(
+            formatted,
+            "name=foo\nversion=1.0\nreal_version=0\n\n\
+             build={\n    cat <<EOF > foo.conf\n  indented\n\nEOF\n    make\n}\n"
+        );
+    }
+
+    #[test]
+    fn idempotent() {
+        for input in [
+            include_str!("../../doc/pkgbuild.bpb"),
+            "version=1.0\nname=foo\n  build = {\n  cat <<EOF\n a\n\nEOF\n}\n# trailing\n",
+        ] {
+            let formatted = format_str(input);
+            assert_eq!(format_str(&formatted), formatted);
+        }
+    }
+}
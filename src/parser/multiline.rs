@@ -1,6 +1,227 @@
-use super::util::*;
 use std::{io::Error, io::ErrorKind, slice::Iter};
 
+/// The lexical contexts the block scanner can be in
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Context {
+    /// A `{}` group: the block itself, a shell group or a brace expansion
+    Brace,
+    /// A `${...}` parameter expansion
+    Param,
+    /// A `$(...)` command substitution
+    Subst,
+    /// A `$((...))` arithmetic expansion or a `((...))` arithmetic command
+    Arith,
+    /// A `(...)` group within an arithmetic expression
+    ArithGroup,
+    /// A `` `...` `` command substitution
+    Backtick,
+    /// A `"..."` string
+    DoubleQuote,
+    /// A `'...'` string
+    SingleQuote,
+}
+
+/// Scans the lines of a `{}` block for its closing brace while respecting shell syntax.
+///
+/// Braces within quotes, escapes, `${...}`, `$(...)`, `$((...))`, heredocs and comments
+/// do not influence the block, only the real closing brace terminates it.
+#[derive(Debug, Default)]
+pub struct BlockScanner {
+    stack: Vec<Context>,
+    heredocs: Vec<String>,
+}
+
+impl BlockScanner {
+    /// Creates a new scanner that expects the opening brace of the block in the first line
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns whether the next line belongs to the body of a heredoc, its delimiter included
+    pub fn in_heredoc(&self) -> bool {
+        !self.heredocs.is_empty()
+    }
+
+    /// Scans the next line of the block.
+    ///
+    /// Returns the byte position of the closing brace of the block if it is in this line
+    /// # Arguments
+    /// * `line` - The line to scan
+    pub fn scan_line(&mut self, line: &str) -> Option<usize> {
+        // Lines of a heredoc body are opaque until the delimiter line
+        if let Some(delimiter) = self.heredocs.first() {
+            if line.trim() == delimiter {
+                self.heredocs.remove(0);
+            }
+            return None;
+        }
+
+        let chars: Vec<(usize, char)> = line.char_indices().collect();
+        let mut i = 0;
+
+        while i < chars.len() {
+            let (pos, c) = chars[i];
+            let next = chars.get(i + 1).map(|c| c.1);
+
+            match self.stack.last() {
+                Some(Context::SingleQuote) => {
+                    if c == '\'' {
+                        self.stack.pop();
+                    }
+                }
+                Some(Context::Backtick) => match c {
+                    '\\' => i += 1,
+                    '`' => {
+                        self.stack.pop();
+                    }
+                    _ => {}
+                },
+                Some(Context::DoubleQuote) => match c {
+                    '\\' => i += 1,
+                    '"' => {
+                        self.stack.pop();
+                    }
+                    '`' => self.stack.push(Context::Backtick),
+                    '$' if next == Some('{') => {
+                        self.stack.push(Context::Param);
+                        i += 1;
+                    }
+                    '$' if next == Some('(') => {
+                        i = self.scan_subst_start(&chars, i + 1);
+                    }
+                    _ => {}
+                },
+                // Arithmetic has no heredocs, comments or strings, `<<` is a shift
+                Some(Context::Arith) | Some(Context::ArithGroup) => match c {
+                    '$' if next == Some('{') => {
+                        self.stack.push(Context::Param);
+                        i += 1;
+                    }
+                    '$' if next == Some('(') => {
+                        i = self.scan_subst_start(&chars, i + 1);
+                    }
+                    '(' => self.stack.push(Context::ArithGroup),
+                    ')' if self.stack.last() == Some(&Context::ArithGroup) => {
+                        self.stack.pop();
+                    }
+                    ')' if next == Some(')') => {
+                        self.stack.pop();
+                        i += 1;
+                    }
+                    _ => {}
+                },
+                top => match c {
+                    '\\' => i += 1,
+                    '\'' => self.stack.push(Context::SingleQuote),
+                    '"' => self.stack.push(Context::DoubleQuote),
+                    '`' => self.stack.push(Context::Backtick),
+                    // A comment only starts at the beginning of a word
+                    '#' if i == 0 || is_word_boundary(chars[i - 1].1) => break,
+                    '$' if next == Some('{') => {
+                        self.stack.push(Context::Param);
+                        i += 1;
+                    }
+                    '$' if next == Some('(') => {
+                        i = self.scan_subst_start(&chars, i + 1);
+                    }
+                    // `((` at the start of a word is an arithmetic command
+                    '(' if next == Some('(') && (i == 0 || is_word_boundary(chars[i - 1].1)) => {
+                        self.stack.push(Context::Arith);
+                        i += 1;
+                    }
+                    '(' if top == Some(&Context::Subst) => self.stack.push(Context::Subst),
+                    ')' if top == Some(&Context::Subst) => {
+                        self.stack.pop();
+                    }
+                    '{' => self.stack.push(Context::Brace),
+                    '}' if matches!(top, Some(Context::Brace) | Some(Context::Param)) => {
+                        self.stack.pop();
+                        if self.stack.is_empty() {
+                            return Some(pos);
+                        }
+                    }
+                    '<' if next == Some('<') => {
+                        i = self.scan_heredoc_start(&chars, i);
+                    }
+                    _ => {}
+                },
+            }
+
+            i += 1;
+        }
+
+        None
+    }
+
+    /// Enters the substitution opened by the `$(` whose `(` is at `start`:
+    /// An arithmetic expansion if it is followed by another `(`, a command substitution otherwise.
+    ///
+    /// Returns the index of the last character belonging to the opening
+    /// # Arguments
+    /// * `chars` - The characters of the line
+    /// * `start` - The index of the `(` after the `$`
+    fn scan_subst_start(&mut self, chars: &[(usize, char)], start: usize) -> usize {
+        if chars.get(start + 1).map(|c| c.1) == Some('(') {
+            self.stack.push(Context::Arith);
+            start + 1
+        } else {
+            self.stack.push(Context::Subst);
+            start
+        }
+    }
+
+    /// Parses the delimiter of a heredoc starting at the `<<` at `start`.
+    ///
+    /// Returns the index of the last character belonging to the heredoc operator
+    /// # Arguments
+    /// * `chars` - The characters of the line
+    /// * `start` - The index of the first `<`
+    fn scan_heredoc_start(&mut self, chars: &[(usize, char)], start: usize) -> usize {
+        let mut i = start + 2;
+
+        // `<<<` is a here-string, not a heredoc
+        if chars.get(i).map(|c| c.1) == Some('<') {
+            return i;
+        }
+
+        if chars.get(i).map(|c| c.1) == Some('-') {
+            i += 1;
+        }
+
+        while chars.get(i).is_some_and(|c| c.1 == ' ' || c.1 == '\t') {
+            i += 1;
+        }
+
+        let mut delimiter = String::new();
+        let mut quote: Option<char> = None;
+        while let Some((_, c)) = chars.get(i) {
+            match (quote, c) {
+                (Some(q), c) if q == *c => quote = None,
+                (Some(_), c) => delimiter.push(*c),
+                (None, '\'' | '"') => quote = Some(*c),
+                (None, '\\') => {}
+                (None, c) if is_word_boundary(*c) || *c == '<' || *c == '>' => break,
+                (None, c) => delimiter.push(*c),
+            }
+            i += 1;
+        }
+
+        if delimiter.is_empty() {
+            return start + 1;
+        }
+
+        self.heredocs.push(delimiter);
+        i - 1
+    }
+}
+
+/// Returns whether the supplied character ends a shell word
+/// # Arguments
+/// * `c` - The character to check
+fn is_word_boundary(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ';' | '&' | '|' | '(' | ')')
+}
+
 /// Iterates over multiple lines to parse a Vec of Strings wrapped in `{}`
 /// # Arguments
 /// * `iter` - The iterator to iterate
@@ -8,44 +229,43 @@ use std::{io::Error, io::ErrorKind, slice::Iter};
 pub fn parse_multiline(iter: &mut Iter<&str>, start_line: &str) -> Result<Vec<String>, Error> {
     let mut lines: Vec<String> = vec![];
     let mut line = start_line.to_string();
+    let mut scanner = BlockScanner::new();
 
-    if char_occurrences(&line, '{').is_empty() {
+    if !line.starts_with('{') {
         return Err(Error::new(
             ErrorKind::InvalidData,
             "Tried to parse multiline, but no opening brace in start line",
         ));
     }
 
-    //There is 1 opening brace we know of, and we remove it
-    let mut ob = 1;
-    let mut cb = 0;
-    line.remove_first('{');
+    //Skip the opening brace in the first line
+    let mut offset = 1;
 
     //Now loop over the following lines
     loop {
-        //Trim the line and append opening and closing braces
-        line = line.trim().to_string();
-        ob += char_occurrences(&line, '{').len();
-        cb += char_occurrences(&line, '}').len();
-
-        //If the block is finished (same openings as closings)
-        if ob == cb {
-            //Remove the last closing brace
-            line.remove_last('}');
-
-            //Push the line if possible and return
-            if !line.is_empty() {
-                lines.push(line);
+        //If the block is finished, push the rest of the line and return
+        if let Some(pos) = scanner.scan_line(&line) {
+            let content = line[offset..pos].trim();
+            if !content.is_empty() {
+                lines.push(content.to_string());
+            }
+
+            let trailing = line[pos + 1..].trim();
+            if !trailing.is_empty() && !trailing.starts_with('#') {
+                warn!("Ignoring trailing content after instruction block: '{trailing}'");
             }
+
             return Ok(lines);
         }
 
         //Only push lines that aren't empty
-        if !line.is_empty() {
-            lines.push(line);
+        let content = line[offset..].trim();
+        if !content.is_empty() {
+            lines.push(content.to_string());
         }
 
         //Get the next line
+        offset = 0;
         line = match iter.next() {
             Some(l) => l.to_string(),
             None => {
@@ -54,3 +274,80 @@ pub fn parse_multiline(iter: &mut Iter<&str>, start_line: &str) -> Result<Vec<St
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lines: &[&str]) -> Result<Vec<String>, Error> {
+        let mut iter = lines[1..].iter();
+        parse_multiline(&mut iter, lines[0])
+    }
+
+    #[test]
+    fn arithmetic_shift() {
+        assert_eq!(
+            parse(&["{ echo $((1<<2)) }"]).unwrap(),
+            vec!["echo $((1<<2))"]
+        );
+        assert_eq!(
+            parse(&["{", "(( x = (1 << 2) + 1 ))", "echo $x", "}"]).unwrap(),
+            vec!["(( x = (1 << 2) + 1 ))", "echo $x"]
+        );
+    }
+
+    #[test]
+    fn arithmetic_groups() {
+        assert_eq!(
+            parse(&["{ echo $(( (1<<2) * (3) )) }"]).unwrap(),
+            vec!["echo $(( (1<<2) * (3) ))"]
+        );
+        assert_eq!(
+            parse(&["{ echo $((${A:-1}<<$(echo 2))) }"]).unwrap(),
+            vec!["echo $((${A:-1}<<$(echo 2)))"]
+        );
+    }
+
+    #[test]
+    fn nested_substitutions() {
+        assert_eq!(
+            parse(&["{ echo $(echo $(echo }) \"$( { echo; } )\") }"]).unwrap(),
+            vec!["echo $(echo $(echo }) \"$( { echo; } )\")"]
+        );
+        assert_eq!(
+            parse(&["{ echo `echo }` ${X:-}}"]).unwrap(),
+            vec!["echo `echo }` ${X:-}"]
+        );
+    }
+
+    #[test]
+    fn quoted_braces() {
+        assert_eq!(
+            parse(&["{", "echo '}' \"}\" \\}", "}"]).unwrap(),
+            vec!["echo '}' \"}\" \\}"]
+        );
+        assert_eq!(
+            parse(&["{", "echo hi # }", "}"]).unwrap(),
+            vec!["echo hi # }"]
+        );
+    }
+
+    #[test]
+    fn heredocs() {
+        assert_eq!(
+            parse(&["{", "cat <<-EOF", "\t}", "\tEOF", "}"]).unwrap(),
+            vec!["cat <<-EOF", "}", "EOF"]
+        );
+        assert_eq!(
+            parse(&["{", "cat << 'END' > a", "}", "END", "cat <<< x", "}"]).unwrap(),
+            vec!["cat << 'END' > a", "}", "END", "cat <<< x"]
+        );
+    }
+
+    #[test]
+    fn unterminated() {
+        assert!(parse(&["{ echo $((1<<2)"]).is_err());
+        assert!(parse(&["{", "cat <<EOF", "}"]).is_err());
+        assert!(parse(&["echo"]).is_err());
+    }
+}
@@ -0,0 +1,485 @@
+use super::{
+    find_bundle_file, map_pkgbuild, read_entries, read_file, report_warnings, ParseMode,
+    ParseResult, ParseWarning, ParseWarningKind,
+};
+use crate::{Dependency, PackageBuild, CHECKSUM_SKIP};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Error, ErrorKind, Read},
+    path::{Path, PathBuf},
+};
+
+/// The keys of arrays that get appended to the template's array instead of replacing it
+const APPEND_KEYS: &[&str] = &[
+    "provides",
+    "extra_sources",
+    "extra_dependencies",
+    "optional_dependencies",
+    "build_dependencies",
+    "cross_dependencies",
+];
+
+/// The appended keys holding dependencies, merged by the name of the package depended on
+const DEPENDENCY_KEYS: &[&str] = &[
+    "extra_dependencies",
+    "optional_dependencies",
+    "build_dependencies",
+    "cross_dependencies",
+];
+
+/// Resolves the `inherit` key of packagebuilds by merging in templates.
+///
+/// A template is a (partial) packagebuild at `<template dir>/<name>.bpb` that can
+/// inherit from another template itself. The inheriting packagebuild overrides the
+/// values of the template, except for arrays of sources and dependencies, which
+/// get appended to the template's arrays. A dependency on a package the template already
+/// depends on replaces the template's one.
+///
+/// The resolved packagebuild does not inherit anymore, its `inherit` is unset.
+/// The templates of the builder are in `BuilderConfiguration::get_templates_dir()`.
+pub struct TemplateResolver {
+    template_dir: PathBuf,
+}
+
+impl TemplateResolver {
+    /// Creates a new resolver that looks for templates in the supplied directory
+    /// # Arguments
+    /// * `template_dir` - The directory containing the templates
+    pub fn new(template_dir: &Path) -> Self {
+        Self {
+            template_dir: template_dir.to_owned(),
+        }
+    }
+
+    /// Parses a packagebuild from the supplied input and resolves its templates
+    /// # Arguments
+    /// * `input` - The packagebuild as an input implementing Read
+    pub fn parse<R: Read>(&self, input: &mut R) -> Result<PackageBuild, Error> {
+        let (pkgbuild, warnings) = self.parse_with_mode(input, ParseMode::Lenient)?;
+        report_warnings(warnings)?;
+
+        Ok(pkgbuild)
+    }
+
+    /// Parses the packagebuild file at the supplied path and resolves its templates,
+    /// see `parser::parse_file()`
+    /// # Arguments
+    /// * `path` - The path of the packagebuild file
+    pub fn parse_file(&self, path: &Path) -> Result<PackageBuild, Error> {
+        read_file(path, |file| self.parse(file))
+    }
+
+    /// Loads the packagebuild bundle in the supplied directory and resolves its templates,
+    /// see `PackageBuild::load_dir()`
+    /// # Arguments
+    /// * `dir` - The directory of the bundle
+    pub fn load_dir(&self, dir: &Path) -> Result<PackageBuild, Error> {
+        self.parse_file(&find_bundle_file(dir)?)
+    }
+
+    /// Parses a packagebuild from the supplied input using the supplied mode
+    /// and resolves its templates.
+    ///
+    /// The returned warnings refer to the packagebuild, not to the templates
+    /// # Arguments
+    /// * `input` - The packagebuild as an input implementing Read
+    /// * `mode` - The mode to parse the packagebuild and templates in
+    pub fn parse_with_mode<R: Read>(
+        &self,
+        input: &mut R,
+        mode: ParseMode,
+    ) -> Result<(PackageBuild, Vec<ParseWarning>), Error> {
+        let (entries, warnings) = read_entries(input, mode)?;
+        let mut entries = self.resolve(entries, mode, &mut Vec::new())?;
+        entries.remove("inherit");
+
+        Ok((map_pkgbuild(&entries)?, warnings))
+    }
+
+    /// Merges the template referenced by the entries (if any) into the entries
+    /// # Arguments
+    /// * `entries` - The entries to resolve
+    /// * `mode` - The mode to parse templates in
+    /// * `chain` - The templates that are currently being resolved, to detect cycles
+    fn resolve(
+        &self,
+        entries: HashMap<String, ParseResult>,
+        mode: ParseMode,
+        chain: &mut Vec<String>,
+    ) -> Result<HashMap<String, ParseResult>, Error> {
+        let name = match entries.get("inherit") {
+            None => return Ok(entries),
+            Some(ParseResult::String(name)) => name.clone(),
+            Some(ParseResult::Vec(_)) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Expected String, got Vec for key 'inherit'",
+                ))
+            }
+        };
+
+        if chain.contains(&name) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Cyclic template inheritance: {} -> {name}",
+                    chain.join(" -> ")
+                ),
+            ));
+        }
+
+        let template = self.load(&name, mode)?;
+        chain.push(name);
+        let template = self.resolve(template, mode, chain)?;
+        chain.pop();
+
+        Ok(merge(template, entries))
+    }
+
+    /// Loads the entries of the template with the supplied name
+    /// # Arguments
+    /// * `name` - The name of the template
+    /// * `mode` - The mode to parse the template in
+    fn load(&self, name: &str, mode: ParseMode) -> Result<HashMap<String, ParseResult>, Error> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid template name '{name}'"),
+            ));
+        }
+
+        let path = self.template_dir.join(format!("{name}.bpb"));
+        debug!(
+            "Loading template '{}' from {}",
+            name,
+            path.to_string_lossy()
+        );
+
+        let mut file = File::open(&path).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!(
+                    "When opening template '{name}' at {}: {e}",
+                    path.to_string_lossy()
+                ),
+            )
+        })?;
+
+        // Templates can not be checked by their users, so their invalid values are errors
+        let (entries, warnings) = read_entries(&mut file, mode)?;
+        for warning in warnings {
+            match warning.kind {
+                ParseWarningKind::InvalidValue(_) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Template '{name}': {warning}"),
+                    ))
+                }
+                _ => warn!("Template '{name}': {warning}"),
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Merges the entries of a packagebuild into the entries of its template
+/// # Arguments
+/// * `template` - The entries of the template
+/// * `entries` - The entries of the inheriting packagebuild
+fn merge(
+    mut template: HashMap<String, ParseResult>,
+    mut entries: HashMap<String, ParseResult>,
+) -> HashMap<String, ParseResult> {
+    merge_extra_sources(&mut template, &mut entries);
+
+    for (key, value) in entries {
+        match (template.get_mut(&key), value) {
+            (Some(ParseResult::Vec(base)), ParseResult::Vec(items))
+                if APPEND_KEYS.contains(&key.as_str()) =>
+            {
+                let dependencies = DEPENDENCY_KEYS.contains(&key.as_str());
+                for item in items {
+                    let existing = match dependencies {
+                        true => base
+                            .iter()
+                            .position(|b| dependency_name(b) == dependency_name(&item)),
+                        false => base.iter().position(|b| *b == item),
+                    };
+
+                    match existing {
+                        Some(pos) => base[pos] = item,
+                        None => base.push(item),
+                    }
+                }
+            }
+            (_, value) => {
+                template.insert(key, value);
+            }
+        }
+    }
+
+    template
+}
+
+/// Returns the name of the package the supplied dependency depends on,
+/// the dependency itself if it can not be parsed
+/// # Arguments
+/// * `dependency` - The dependency as written in the packagebuild
+fn dependency_name(dependency: &str) -> String {
+    match dependency.parse::<Dependency>() {
+        Ok(dependency) => dependency.name,
+        Err(_) => dependency.to_owned(),
+    }
+}
+
+/// Appends the extra sources of the inheriting packagebuild to the template's ones together
+/// with their checksums, so `extra_sources_sha256` stays aligned with `extra_sources`.
+///
+/// Sources without a checksum get `SKIP`, a source the template already has keeps its
+/// position and takes the checksum of the inheriting packagebuild, if it has one.
+/// Takes the merged keys out of the entries
+/// # Arguments
+/// * `template` - The entries of the template
+/// * `entries` - The entries of the inheriting packagebuild
+fn merge_extra_sources(
+    template: &mut HashMap<String, ParseResult>,
+    entries: &mut HashMap<String, ParseResult>,
+) {
+    let (base, items) = match (template.get("extra_sources"), entries.get("extra_sources")) {
+        (Some(ParseResult::Vec(base)), Some(ParseResult::Vec(items))) => {
+            (base.clone(), items.clone())
+        }
+        _ => return,
+    };
+
+    let checksums =
+        |entries: &HashMap<String, ParseResult>| match entries.get("extra_sources_sha256") {
+            Some(ParseResult::Vec(checksums)) => Some(checksums.clone()),
+            _ => None,
+        };
+    let base_checksums = checksums(template);
+    let item_checksums = checksums(entries);
+    let has_checksums = base_checksums.is_some() || item_checksums.is_some();
+
+    let checksum = |checksums: &Option<Vec<String>>, i: usize| {
+        checksums
+            .as_ref()
+            .and_then(|c| c.get(i))
+            .cloned()
+            .unwrap_or_else(|| CHECKSUM_SKIP.to_owned())
+    };
+
+    let mut sources = base.clone();
+    let mut merged: Vec<String> = (0..base.len())
+        .map(|i| checksum(&base_checksums, i))
+        .collect();
+
+    for (i, item) in items.into_iter().enumerate() {
+        let item_checksum = checksum(&item_checksums, i);
+
+        match sources.iter().position(|s| *s == item) {
+            Some(pos) if item_checksum != CHECKSUM_SKIP => merged[pos] = item_checksum,
+            Some(_) => {}
+            None => {
+                sources.push(item);
+                merged.push(item_checksum);
+            }
+        }
+    }
+
+    entries.remove("extra_sources");
+    entries.remove("extra_sources_sha256");
+    template.insert("extra_sources".to_owned(), ParseResult::Vec(sources));
+    if has_checksums {
+        template.insert("extra_sources_sha256".to_owned(), ParseResult::Vec(merged));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const C: &str = "cccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccc";
+
+    fn resolve(test: &str, template: &str, pkgbuild: &str) -> Result<PackageBuild, Error> {
+        resolve_templates(test, &[("base", template)], pkgbuild)
+    }
+
+    fn resolve_templates(
+        test: &str,
+        templates: &[(&str, &str)],
+        pkgbuild: &str,
+    ) -> Result<PackageBuild, Error> {
+        let dir =
+            std::env::temp_dir().join(format!("pkgbuild-template-{test}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        for (name, template) in templates {
+            std::fs::write(dir.join(format!("{name}.bpb")), template)?;
+        }
+
+        let res = TemplateResolver::new(&dir).parse(
+            &mut format!(
+                "name=foo
+version=1.0
+real_version=0
+inherit=base
+{pkgbuild}"
+            )
+            .as_bytes(),
+        );
+        std::fs::remove_dir_all(&dir)?;
+        res
+    }
+
+    #[test]
+    fn appends_checksums_in_lockstep() {
+        let pkgbuild = resolve(
+            "lockstep",
+            &format!(
+                "extra_sources=[https://a/a][https://a/b]\nextra_sources_sha256=[{A}][SKIP]\n"
+            ),
+            &format!("extra_sources=[https://a/c]\nextra_sources_sha256=[{C}]\n"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            pkgbuild.extra_sources.unwrap(),
+            vec!["https://a/a", "https://a/b", "https://a/c"]
+        );
+        assert_eq!(pkgbuild.extra_sources_sha256.unwrap(), vec![A, "SKIP", C]);
+    }
+
+    #[test]
+    fn pads_missing_checksums_with_skip() {
+        let pkgbuild = resolve(
+            "pad1",
+            &format!("extra_sources=[https://a/a]\nextra_sources_sha256=[{A}]\n"),
+            "extra_sources=[https://a/c]\n",
+        )
+        .unwrap();
+        assert_eq!(pkgbuild.extra_sources_sha256.unwrap(), vec![A, "SKIP"]);
+
+        let pkgbuild = resolve(
+            "pad2",
+            "extra_sources=[https://a/a]\n",
+            &format!("extra_sources=[https://a/c]\nextra_sources_sha256=[{C}]\n"),
+        )
+        .unwrap();
+        assert_eq!(pkgbuild.extra_sources_sha256.unwrap(), vec!["SKIP", C]);
+    }
+
+    #[test]
+    fn duplicate_sources_keep_their_position() {
+        let pkgbuild = resolve(
+            "duplicates",
+            "extra_sources=[https://a/a][https://a/b]\n",
+            &format!(
+                "extra_sources=[https://a/a][https://a/c]\nextra_sources_sha256=[{A}][SKIP]\n"
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            pkgbuild.extra_sources.unwrap(),
+            vec!["https://a/a", "https://a/b", "https://a/c"]
+        );
+        assert_eq!(
+            pkgbuild.extra_sources_sha256.unwrap(),
+            vec![A, "SKIP", "SKIP"]
+        );
+    }
+
+    #[test]
+    fn sources_without_checksums() {
+        let pkgbuild = resolve(
+            "plain",
+            "extra_sources=[https://a/a]\nprovides=[x]\n",
+            "extra_sources=[https://a/c]\nprovides=[y]\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            pkgbuild.extra_sources.unwrap(),
+            vec!["https://a/a", "https://a/c"]
+        );
+        assert_eq!(pkgbuild.extra_sources_sha256, None);
+        assert_eq!(pkgbuild.provides.unwrap(), vec!["x", "y"]);
+    }
+
+    #[test]
+    fn overrides() {
+        let pkgbuild = resolve(
+            "overrides",
+            "description=Template\nstrip=0\nbuild={\n  make\n}\ncheck={\n  make check\n}\n",
+            "description=Foo\nbuild={\n  make foo\n}\n",
+        )
+        .unwrap();
+
+        assert_eq!(pkgbuild.description.as_deref(), Some("Foo"));
+        assert_eq!(pkgbuild.strip, Some(false));
+        assert_eq!(pkgbuild.build, Some(vec!["make foo".to_owned()]));
+        assert_eq!(pkgbuild.check, Some(vec!["make check".to_owned()]));
+        assert_eq!(pkgbuild.inherit, None);
+    }
+
+    #[test]
+    fn dependencies_merge_by_name() {
+        let pkgbuild = resolve(
+            "dependencies",
+            "build_dependencies=[cmake][ninja]\noptional_dependencies=[sqlite: Database]\n",
+            "build_dependencies=[cmake>=3.20][pkgconf]\noptional_dependencies=[sqlite: Cache]\n",
+        )
+        .unwrap();
+
+        let specs = |deps: Option<Vec<Dependency>>| -> Vec<String> {
+            deps.unwrap().iter().map(|d| d.to_string()).collect()
+        };
+        assert_eq!(
+            specs(pkgbuild.build_dependencies),
+            vec!["cmake>=3.20", "ninja", "pkgconf"]
+        );
+        assert_eq!(specs(pkgbuild.optional_dependencies), vec!["sqlite: Cache"]);
+    }
+
+    #[test]
+    fn nested_templates() {
+        let pkgbuild = resolve_templates(
+            "nested",
+            &[
+                ("base", "inherit=core\nbuild_system=cmake\nprovides=[b]\n"),
+                (
+                    "core",
+                    "description=Core\nbuild_system=make\nprovides=[a]\n",
+                ),
+            ],
+            "provides=[c]\n",
+        )
+        .unwrap();
+
+        assert_eq!(pkgbuild.description.as_deref(), Some("Core"));
+        assert_eq!(pkgbuild.build_system.unwrap().to_string(), "cmake");
+        assert_eq!(pkgbuild.provides.unwrap(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn cyclic_templates() {
+        let err = resolve_templates(
+            "cycle",
+            &[("base", "inherit=core\n"), ("core", "inherit=base\n")],
+            "",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("base -> core -> base"), "{err}");
+
+        assert!(resolve("self", "inherit=base\n", "").is_err());
+        assert!(resolve("missing", "inherit=missing\n", "").is_err());
+    }
+
+    #[test]
+    fn invalid_template_values() {
+        assert!(resolve("invalid", "strip=yes\n", "").is_err());
+    }
+}
@@ -6,51 +6,119 @@ use std::{
     io::{Error, ErrorKind},
 };
 
-/// Returns a Vec containing the positions of the character in the string
-/// # Arguments
-/// * `string` - The string to search
-/// * `character` - The caracter to search for
-pub fn char_occurrences(string: &str, character: char) -> Vec<usize> {
-    let mut res: Vec<usize> = vec![];
-
-    for (i, ch) in string.char_indices() {
-        if ch == character {
-            res.push(i);
-        }
-    }
-
-    res
-}
-
 /// Parses an array in the following form from the supplied line: `[item][item]`
+///
+/// Within items, `\[`, `\]` and `\\` escape the brackets and the backslash,
+/// a backslash before any other character is kept as it is.
+/// Anything but whitespace between the items is rejected.
 /// # Argument
 /// * `line` - The line to parse
 pub fn parse_array(line: &str) -> Result<Vec<String>, Error> {
     let mut res: Vec<String> = vec![];
-    let mut buf = String::new();
+    let mut buf: Option<String> = None;
+    let mut chars = line.chars();
 
-    for character in line.chars() {
-        match character {
-            '[' => {
-                buf.clear();
+    while let Some(character) = chars.next() {
+        match (&mut buf, character) {
+            (Some(b), '\\') => match chars.next() {
+                Some(c @ ('[' | ']' | '\\')) => b.push(c),
+                // Other backslashes are literal, as they were before escaping existed
+                Some(c) => {
+                    b.push('\\');
+                    b.push(c);
+                }
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "While parsing escape sequence in array",
+                    ))
+                }
+            },
+            (Some(_), '[') => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unescaped '[' within array item in '{line}'"),
+                ))
             }
-            ']' => {
-                res.push(buf.clone());
-                buf.clear();
+            (Some(b), ']') => {
+                res.push(std::mem::take(b));
+                buf = None;
             }
-            c => {
-                buf.push(c);
+            (Some(b), c) => b.push(c),
+            (None, '[') => buf = Some(String::new()),
+            (None, c) if c.is_whitespace() => {}
+            (None, c) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unexpected '{c}' outside of array item in '{line}'"),
+                ))
             }
         }
     }
 
-    if !buf.is_empty() {
+    if buf.is_some() {
         Err(Error::new(ErrorKind::UnexpectedEof, "While parsing array"))
     } else {
         Ok(res)
     }
 }
 
+/// Splits a trailing comment off the value of an entry, the text following its `=`.
+///
+/// A `#` preceded by whitespace starts a comment that runs to the end of the line,
+/// in arrays, every `#` outside of the items does. Other `#`, such as the fragments
+/// of source URLs or the ones within array items, are part of the value.
+/// # Arguments
+/// * `value` - The value to split
+pub fn split_comment(value: &str) -> (&str, Option<&str>) {
+    let array = value.trim_start().starts_with('[');
+    let mut in_item = false;
+    let mut escaped = false;
+    let mut after_whitespace = false;
+
+    for (i, character) in value.char_indices() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' if in_item => escaped = true,
+            '[' if array => in_item = true,
+            ']' if array => in_item = false,
+            '#' if !in_item && (array || after_whitespace) => {
+                return (&value[..i], Some(&value[i..]));
+            }
+            _ => {}
+        }
+        after_whitespace = character.is_whitespace();
+    }
+
+    (value, None)
+}
+
+/// Escapes an array item so it can be parsed back by `parse_array()`
+/// # Arguments
+/// * `item` - The item to escape
+pub fn escape_array_item(item: &str) -> String {
+    let mut res = String::with_capacity(item.len());
+
+    for character in item.chars() {
+        if matches!(character, '[' | ']' | '\\') {
+            res.push('\\');
+        }
+        res.push(character);
+    }
+
+    res
+}
+
+/// Serializes items to an array in the form `[item][item]`, the inverse of `parse_array()`
+/// # Arguments
+/// * `items` - The items to serialize
+pub fn serialize_array(items: &[String]) -> String {
+    items
+        .iter()
+        .map(|item| format!("[{}]", escape_array_item(item)))
+        .collect()
+}
+
 /// A trait for convienient removal functions
 pub trait Remove {
     /// Removes the first occurrence of the supplied character
@@ -168,3 +236,60 @@ impl GetExt for HashMap<String, ParseResult> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_arrays() {
+        assert_eq!(parse_array("[a][b c]").unwrap(), items(&["a", "b c"]));
+        assert_eq!(parse_array(" [a]  [b] ").unwrap(), items(&["a", "b"]));
+        assert_eq!(parse_array("[]").unwrap(), items(&[""]));
+        assert_eq!(parse_array("").unwrap(), items(&[]));
+    }
+
+    #[test]
+    fn parse_escaped_arrays() {
+        assert_eq!(
+            parse_array(r"[a\]b][\[x\]][c\\]").unwrap(),
+            items(&["a]b", "[x]", r"c\"])
+        );
+        // Unknown escapes stay literal, such as in regular expressions
+        assert_eq!(
+            parse_array(r"[\d+][C:\temp]").unwrap(),
+            items(&[r"\d+", r"C:\temp"])
+        );
+        assert!(parse_array(r"[a\").is_err());
+        assert!(parse_array("[a[b]").is_err());
+    }
+
+    #[test]
+    fn reject_garbage_outside_items() {
+        assert!(parse_array("[a]x[b]").is_err());
+        assert!(parse_array("a[b]").is_err());
+        assert!(parse_array("[a]]").is_err());
+        assert!(parse_array("[a").is_err());
+    }
+
+    #[test]
+    fn trailing_comments() {
+        assert_eq!(split_comment("foo # bar"), ("foo ", Some("# bar")));
+        assert_eq!(split_comment("foo#bar"), ("foo#bar", None));
+        assert_eq!(split_comment("[a #b][c] # d"), ("[a #b][c] ", Some("# d")));
+        assert_eq!(split_comment(r"[a\]#b]#c"), (r"[a\]#b]", Some("#c")));
+        assert_eq!(split_comment("{ echo # a"), ("{ echo ", Some("# a")));
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let values = items(&["plain", "a]b", "[x]", r"c\", r"\]", r"\d", ""]);
+        let serialized = serialize_array(&values);
+        assert_eq!(serialized, r"[plain][a\]b][\[x\]][c\\][\\\]][\\d][]");
+        assert_eq!(parse_array(&serialized).unwrap(), values);
+    }
+}
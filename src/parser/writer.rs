@@ -0,0 +1,334 @@
+use super::multiline::BlockScanner;
+use super::util::{serialize_array, split_comment};
+use crate::{check_arch, check_name, ArchOverride, Dependency, PackageBuild, SplitPackage};
+use std::io::{Error, ErrorKind, Write};
+
+impl PackageBuild {
+    /// Serializes this packagebuild to its canonical `.bpb` text, see `parser::write()`
+    pub fn to_bpb(&self) -> Result<String, Error> {
+        let mut output: Vec<u8> = Vec::new();
+        write(self, &mut output)?;
+
+        String::from_utf8(output).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+/// Writes a packagebuild in its canonical `.bpb` form to the supplied output.
+///
+/// The keys are written in a fixed order, arrays in the `[a][b]` form and scripts in `{}` blocks.
+/// Empty strings and arrays are omitted, as the parser treats them as absent.
+/// Values that can not be parsed back to the same value are rejected.
+/// # Arguments
+/// * `pkgbuild` - The packagebuild to write
+/// * `output` - The output to write to
+pub fn write<W: Write>(pkgbuild: &PackageBuild, output: &mut W) -> Result<(), Error> {
+    write_str(output, "name", &pkgbuild.name)?;
+    write_str(output, "version", &pkgbuild.version)?;
+    write_str(output, "real_version", &pkgbuild.real_version.to_string())?;
+    write_str_opt(output, "epoch", &pkgbuild.epoch.map(|e| e.to_string()))?;
+    write_str_opt(output, "inherit", &pkgbuild.inherit)?;
+    writeln!(output)?;
+
+    write_str_opt(output, "maintainer", &pkgbuild.maintainer)?;
+    write_str_opt(output, "maintainer_email", &pkgbuild.maintainer_email)?;
+    write_str_opt(output, "description", &pkgbuild.description)?;
+    write_vec_opt(output, "provides", &pkgbuild.provides)?;
+    write_vec_opt(output, "arch", &pkgbuild.arch)?;
+    write_str_opt(output, "source", &pkgbuild.source)?;
+    write_str_opt(output, "source_sha256", &pkgbuild.source_sha256)?;
+    write_vec_opt(output, "extra_sources", &pkgbuild.extra_sources)?;
+    write_vec_opt(
+        output,
+        "extra_sources_sha256",
+        &pkgbuild.extra_sources_sha256,
+    )?;
+    write_deps_opt(output, "extra_dependencies", &pkgbuild.extra_dependencies)?;
+    write_deps_opt(
+        output,
+        "optional_dependencies",
+        &pkgbuild.optional_dependencies,
+    )?;
+    write_deps_opt(output, "build_dependencies", &pkgbuild.build_dependencies)?;
+    write_deps_opt(output, "cross_dependencies", &pkgbuild.cross_dependencies)?;
+    write_str_opt(output, "preinstall", &pkgbuild.preinstall)?;
+    write_str_opt(output, "postinstall", &pkgbuild.postinstall)?;
+    if let Some(strip) = pkgbuild.strip {
+        write_str(output, "strip", if strip { "1" } else { "0" })?;
+    }
+    if let Some(build_system) = &pkgbuild.build_system {
+        write_str(output, "build_system", &build_system.to_string())?;
+    }
+    let split_names = pkgbuild
+        .split_packages
+        .as_ref()
+        .map(|splits| splits.iter().map(|s| s.name.clone()).collect());
+    write_vec_opt(output, "split_packages", &split_names)?;
+
+    let vars = pkgbuild
+        .vars
+        .as_ref()
+        .map(|vars| vars.iter().map(|v| v.to_string()).collect());
+    write_block_opt(output, "vars", &vars)?;
+
+    write_block_opt(output, "prepare", &pkgbuild.prepare)?;
+    write_block_opt(output, "build", &pkgbuild.build)?;
+    write_block_opt(output, "check", &pkgbuild.check)?;
+    write_block_opt(output, "package", &pkgbuild.package)?;
+
+    for arch_override in pkgbuild.arch_overrides.iter().flatten() {
+        write_arch_override(output, arch_override)?;
+    }
+
+    for split in pkgbuild.split_packages.iter().flatten() {
+        write_split_package(output, split)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the `<key>_<arch>` entries of an architecture override
+/// # Arguments
+/// * `output` - The output to write to
+/// * `arch_override` - The architecture override to write
+fn write_arch_override<W: Write>(
+    output: &mut W,
+    arch_override: &ArchOverride,
+) -> Result<(), Error> {
+    // The architecture becomes part of the keys, so it has to be a valid architecture
+    if let Err(e) = check_arch(&arch_override.arch) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid architecture: {e}"),
+        ));
+    }
+    let key = |key: &str| format!("{key}_{}", arch_override.arch);
+
+    // Write the fields first to only separate them by an empty line if there are any
+    let mut fields: Vec<u8> = Vec::new();
+    write_str_opt(&mut fields, &key("source"), &arch_override.source)?;
+    write_str_opt(
+        &mut fields,
+        &key("source_sha256"),
+        &arch_override.source_sha256,
+    )?;
+    let dependency_lists = [
+        ("extra_dependencies", &arch_override.extra_dependencies),
+        (
+            "optional_dependencies",
+            &arch_override.optional_dependencies,
+        ),
+        ("build_dependencies", &arch_override.build_dependencies),
+        ("cross_dependencies", &arch_override.cross_dependencies),
+    ];
+    for (field, list) in dependency_lists {
+        write_deps_opt(&mut fields, &key(field), list)?;
+    }
+    if !fields.is_empty() {
+        writeln!(output)?;
+        output.write_all(&fields)?;
+    }
+
+    write_block_opt(output, &key("prepare"), &arch_override.prepare)?;
+    write_block_opt(output, &key("build"), &arch_override.build)?;
+    write_block_opt(output, &key("check"), &arch_override.check)?;
+    write_block_opt(output, &key("package"), &arch_override.package)
+}
+
+/// Writes the `<key>_<name>` entries of a split package
+/// # Arguments
+/// * `output` - The output to write to
+/// * `split` - The split package to write
+fn write_split_package<W: Write>(output: &mut W, split: &SplitPackage) -> Result<(), Error> {
+    // The name becomes part of the keys, so it has to be a valid package name
+    if let Err(e) = check_name(&split.name) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid split package name: {e}"),
+        ));
+    }
+    let key = |key: &str| format!("{key}_{}", split.name);
+
+    // Write the fields first to only separate them by an empty line if there are any
+    let mut fields: Vec<u8> = Vec::new();
+    write_str_opt(&mut fields, &key("description"), &split.description)?;
+    write_vec_opt(&mut fields, &key("provides"), &split.provides)?;
+    write_deps_opt(
+        &mut fields,
+        &key("extra_dependencies"),
+        &split.extra_dependencies,
+    )?;
+    write_deps_opt(
+        &mut fields,
+        &key("optional_dependencies"),
+        &split.optional_dependencies,
+    )?;
+    write_vec_opt(&mut fields, &key("files"), &split.files)?;
+    if !fields.is_empty() {
+        writeln!(output)?;
+        output.write_all(&fields)?;
+    }
+
+    write_block_opt(output, &key("package"), &split.package)
+}
+
+/// Checks if a string value would parse back as the same string
+/// # Arguments
+/// * `key` - The key of the value
+/// * `value` - The value to check
+pub(super) fn check_str(key: &str, value: &str) -> Result<(), Error> {
+    if value.contains('\n')
+        || value.trim() != value
+        || value.starts_with('[')
+        || value.starts_with('{')
+        || value.starts_with('#')
+        || split_comment(value).1.is_some()
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Value '{value}' for key '{key}' can not be represented"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks if the lines of a block would parse back as the same lines
+/// # Arguments
+/// * `key` - The key of the block
+/// * `lines` - The lines of the block
+pub(super) fn check_block(key: &str, lines: &[String]) -> Result<(), Error> {
+    // Make sure the block closes exactly where we close it
+    let mut scanner = BlockScanner::new();
+    scanner.scan_line("{");
+    for line in lines {
+        if line.is_empty() || line.trim() != line || line.contains('\n') {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Line '{line}' in block '{key}' can not be represented"),
+            ));
+        }
+
+        if scanner.scan_line(line).is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Line '{line}' closes block '{key}' prematurely"),
+            ));
+        }
+    }
+    if scanner.scan_line("}").is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Block '{key}' is not terminated by its closing brace"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Writes a `key=value` line, rejecting values that would not parse back as the same string
+/// # Arguments
+/// * `output` - The output to write to
+/// * `key` - The key to write
+/// * `value` - The value to write
+fn write_str<W: Write>(output: &mut W, key: &str, value: &str) -> Result<(), Error> {
+    check_str(key, value)?;
+    writeln!(output, "{key}={value}")
+}
+
+/// Writes an optional `key=value` line if the value is present and not empty
+/// # Arguments
+/// * `output` - The output to write to
+/// * `key` - The key to write
+/// * `value` - The value to write
+fn write_str_opt<W: Write>(output: &mut W, key: &str, value: &Option<String>) -> Result<(), Error> {
+    match value {
+        Some(v) if !v.is_empty() => write_str(output, key, v),
+        _ => Ok(()),
+    }
+}
+
+/// Writes an optional `key=[a][b]` line if the value is present and not empty
+/// # Arguments
+/// * `output` - The output to write to
+/// * `key` - The key to write
+/// * `value` - The items to write
+fn write_vec_opt<W: Write>(
+    output: &mut W,
+    key: &str,
+    value: &Option<Vec<String>>,
+) -> Result<(), Error> {
+    match value {
+        Some(v) if !v.is_empty() => writeln!(output, "{key}={}", serialize_array(v)),
+        _ => Ok(()),
+    }
+}
+
+/// Writes an optional `key=[a][b]` line of dependencies if the value is present and not empty
+/// # Arguments
+/// * `output` - The output to write to
+/// * `key` - The key to write
+/// * `value` - The dependencies to write
+fn write_deps_opt<W: Write>(
+    output: &mut W,
+    key: &str,
+    value: &Option<Vec<Dependency>>,
+) -> Result<(), Error> {
+    let items = value
+        .as_ref()
+        .map(|deps| deps.iter().map(|d| d.to_string()).collect());
+    write_vec_opt(output, key, &items)
+}
+
+/// Writes an optional `key={}` block if the value is present and not empty
+/// # Arguments
+/// * `output` - The output to write to
+/// * `key` - The key to write
+/// * `value` - The lines of the block
+fn write_block_opt<W: Write>(
+    output: &mut W,
+    key: &str,
+    value: &Option<Vec<String>>,
+) -> Result<(), Error> {
+    let lines = match value {
+        Some(v) if !v.is_empty() => v,
+        _ => return Ok(()),
+    };
+
+    check_block(key, lines)?;
+
+    writeln!(output)?;
+    writeln!(output, "{key}={{")?;
+    for line in lines {
+        writeln!(output, "    {line}")?;
+    }
+    writeln!(output, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn round_trip_escaped_arrays() {
+        let mut pkgbuild = PackageBuild::new("foo", "1.0", 0);
+        pkgbuild.provides = Some(vec!["foo[bin]".to_owned(), r"a\b".to_owned()]);
+        pkgbuild.description = Some("Foo [bar]".to_owned());
+        pkgbuild.build = Some(vec!["echo \"[$1]\"".to_owned()]);
+
+        let bpb = pkgbuild.to_bpb().unwrap();
+        assert!(bpb.contains(r"provides=[foo\[bin\]][a\\b]"));
+        assert_eq!(parse(&mut bpb.as_bytes()).unwrap(), pkgbuild);
+    }
+
+    #[test]
+    fn reject_unparsable_values() {
+        let mut pkgbuild = PackageBuild::new("foo", "1.0", 0);
+        pkgbuild.description = Some("[not an array]".to_owned());
+        assert!(pkgbuild.to_bpb().is_err());
+        pkgbuild.description = Some("Foo # not a comment".to_owned());
+        assert!(pkgbuild.to_bpb().is_err());
+        pkgbuild.description = Some("A C# compiler".to_owned());
+        assert!(pkgbuild.to_bpb().is_ok());
+    }
+}
@@ -0,0 +1,466 @@
+use crate::{is_reference, is_relative_path, StdIOErrorExt};
+use std::{
+    fmt::Display,
+    fs,
+    io::{Error, ErrorKind},
+    path::Path,
+    str::FromStr,
+};
+
+/// The options a source can have in its fragment
+pub const SOURCE_OPTIONS: &[&str] = &["sha256", "extract", "rename", "tag", "commit", "branch"];
+
+/// The prefix of the schemes of git repository sources, such as `git+https://`
+pub const GIT_PREFIX: &str = "git+";
+
+/// The revision of a git repository source to check out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GitRevision {
+    /// The default branch of the repository
+    Default,
+    /// A tag: `#tag=<tag>`
+    Tag(String),
+    /// A full commit hash: `#commit=<sha>`, verified after checking it out
+    Commit(String),
+    /// The head of a branch: `#branch=<branch>`
+    Branch(String),
+}
+
+/// A source of a packagebuild together with the options in its fragment:
+/// `<location>[#<option>=<value>[&<option>=<value>]...]`.
+///
+/// The location is a URL, a reference (a UUID) that gets resolved to a URL
+/// or a path relative to the packagebuild.
+/// Examples: `https://example.org/foo.tar.gz#extract=0` or `<uuid>#rename=foo.conf`.
+///
+/// Git repositories use the URL of the repository prefixed with `git+` and at most one
+/// of the `tag`, `commit` or `branch` options: `git+https://example.org/foo.git#tag=v1.2`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    /// The URL or reference of the source
+    pub location: String,
+    /// The options of the source in the order they were declared
+    pub options: Vec<(String, String)>,
+}
+
+/// Resolves the references in `extra_sources`, such as the UUIDs of a file store, to URLs
+pub trait SourceResolver {
+    /// Returns the URL to fetch the referenced source from
+    /// # Arguments
+    /// * `reference` - The reference to resolve
+    fn resolve(&self, reference: &str) -> Result<String, Error>;
+}
+
+impl Source {
+    /// Creates a new source without any options
+    /// # Arguments
+    /// * `location` - The URL or reference of the source
+    pub fn new(location: &str) -> Self {
+        Self {
+            location: location.to_owned(),
+            options: Vec::new(),
+        }
+    }
+
+    /// Returns the value of the supplied option, if it is set
+    /// # Arguments
+    /// * `key` - The option to get
+    pub fn option(&self, key: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Removes the supplied option and returns its value, if it was set
+    /// # Arguments
+    /// * `key` - The option to remove
+    pub fn take_option(&mut self, key: &str) -> Option<String> {
+        let pos = self.options.iter().position(|(k, _)| k == key)?;
+        Some(self.options.remove(pos).1)
+    }
+
+    /// Checks if the location of this source is a reference that has to be resolved
+    pub fn is_reference(&self) -> bool {
+        is_reference(&self.location)
+    }
+
+    /// Checks if the location of this source is a path within the packagebuild bundle
+    /// rather than a URL or a reference
+    pub fn is_relative(&self) -> bool {
+        !self.location.contains("://") && !self.is_reference()
+    }
+
+    /// Returns the location to fetch this source from: Relative paths are resolved against
+    /// the supplied directory, such as the origin of the packagebuild, and must not leave it,
+    /// URLs and references are returned as they are
+    /// # Arguments
+    /// * `origin` - The directory to resolve relative paths against
+    pub fn resolve_relative(&self, origin: Option<&Path>) -> Result<String, Error> {
+        if !self.is_relative() {
+            return Ok(self.location.clone());
+        }
+
+        if !is_relative_path(&self.location) {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "Source {} must be a path relative to the packagebuild, without '..'",
+                    self.location
+                ),
+            ));
+        }
+
+        let origin = match origin {
+            Some(origin) => fs::canonicalize(origin).err_prepend(&format!(
+                "When resolving the packagebuild directory {}",
+                origin.to_string_lossy()
+            ))?,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "Source {} is a relative path, but the packagebuild has no directory",
+                        self.location
+                    ),
+                ))
+            }
+        };
+
+        // Symlinks are resolved, so they can't point outside of the bundle either
+        let path = fs::canonicalize(origin.join(&self.location))
+            .err_prepend(&format!("When resolving source {}", self.location))?;
+        if !path.starts_with(&origin) {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "Source {} points outside of the packagebuild directory",
+                    self.location
+                ),
+            ));
+        }
+
+        Ok(path.to_string_lossy().into())
+    }
+
+    /// Checks if this source is a git repository (`git+<url>`)
+    pub fn is_git(&self) -> bool {
+        self.location.starts_with(GIT_PREFIX)
+    }
+
+    /// Returns the URL of the git repository without the `git+` prefix, if this is one
+    pub fn git_url(&self) -> Option<&str> {
+        self.location.strip_prefix(GIT_PREFIX)
+    }
+
+    /// Returns the revision of the git repository to check out
+    pub fn git_revision(&self) -> GitRevision {
+        if let Some(tag) = self.option("tag") {
+            GitRevision::Tag(tag.to_owned())
+        } else if let Some(commit) = self.option("commit") {
+            GitRevision::Commit(commit.to_owned())
+        } else if let Some(branch) = self.option("branch") {
+            GitRevision::Branch(branch.to_owned())
+        } else {
+            GitRevision::Default
+        }
+    }
+
+    /// Returns whether the source should be extracted: `None` extracts it if it is an archive
+    pub fn extract(&self) -> Option<bool> {
+        self.option("extract").map(|v| v == "1" || v == "true")
+    }
+
+    /// Returns the name the source should be stored under in the build directory:
+    /// The `rename` option or the last path segment of the supplied URL
+    /// # Arguments
+    /// * `url` - The URL the source is fetched from
+    pub fn file_name<'a>(&'a self, url: &'a str) -> Option<&'a str> {
+        match self.option("rename") {
+            Some(name) => Some(name),
+            None => url.rsplit('/').next().filter(|name| !name.is_empty()),
+        }
+    }
+}
+
+impl Display for GitRevision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Default => write!(f, "the default branch"),
+            Self::Tag(tag) => write!(f, "tag {tag}"),
+            Self::Commit(commit) => write!(f, "commit {commit}"),
+            Self::Branch(branch) => write!(f, "branch {branch}"),
+        }
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.location)?;
+
+        for (i, (key, value)) in self.options.iter().enumerate() {
+            let separator = if i == 0 { '#' } else { '&' };
+            write!(f, "{separator}{key}={value}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+
+        let (location, fragment) = match s.split_once('#') {
+            Some((location, fragment)) => (location, Some(fragment)),
+            None => (s, None),
+        };
+
+        if location.is_empty() {
+            return Err(invalid(format!("Missing location in source '{s}'")));
+        }
+
+        let mut options: Vec<(String, String)> = Vec::new();
+        for option in fragment.iter().flat_map(|f| f.split('&')) {
+            let (key, value) = match option.split_once('=') {
+                Some(v) => v,
+                None => {
+                    return Err(invalid(format!(
+                        "Expected 'option=value' in source '{s}', got '{option}'"
+                    )))
+                }
+            };
+
+            if !SOURCE_OPTIONS.contains(&key) {
+                return Err(invalid(format!("Unknown option '{key}' in source '{s}'")));
+            }
+
+            if options.iter().any(|(k, _)| k == key) {
+                return Err(invalid(format!("Duplicate option '{key}' in source '{s}'")));
+            }
+
+            if ["tag", "commit", "branch"].contains(&key) {
+                if !location.starts_with(GIT_PREFIX) {
+                    return Err(invalid(format!(
+                        "Option '{key}' in source '{s}' is only valid for git sources"
+                    )));
+                }
+
+                if let Some((other, _)) = options
+                    .iter()
+                    .find(|(k, _)| ["tag", "commit", "branch"].contains(&k.as_str()))
+                {
+                    return Err(invalid(format!(
+                        "Options '{other}' and '{key}' in source '{s}' exclude each other"
+                    )));
+                }
+            }
+
+            match key {
+                "commit"
+                    if value.len() != 40 || !value.chars().all(|c| c.is_ascii_hexdigit()) =>
+                {
+                    return Err(invalid(format!(
+                        "Invalid commit hash '{value}' in source '{s}', expected the full 40 hex digits"
+                    )))
+                }
+                "tag" | "branch"
+                    if value.is_empty()
+                        || value.starts_with('-')
+                        || value.chars().any(|c| c.is_whitespace() || c.is_control()) =>
+                {
+                    return Err(invalid(format!("Invalid {key} '{value}' in source '{s}'")))
+                }
+                "extract" if !["0", "1", "true", "false"].contains(&value) => {
+                    return Err(invalid(format!(
+                        "Invalid value '{value}' for extract in source '{s}', expected 1 or 0"
+                    )))
+                }
+                "rename"
+                    if value.is_empty() || value.contains('/') || value == "." || value == ".." =>
+                {
+                    return Err(invalid(format!(
+                        "Invalid file name '{value}' to rename source '{s}' to"
+                    )))
+                }
+                _ => {}
+            }
+
+            options.push((key.to_owned(), value.to_owned()));
+        }
+
+        Ok(Self {
+            location: location.to_owned(),
+            options,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMIT: &str = "0f080a7ab2cb48b6fa38de01f6a7a784e2b54ab5";
+
+    #[test]
+    fn parse_fragments() {
+        let source: Source = "https://example.org/foo.tar.gz".parse().unwrap();
+        assert_eq!(source, Source::new("https://example.org/foo.tar.gz"));
+        assert_eq!(source.extract(), None);
+
+        let source: Source = "https://example.org/foo.tar.gz#extract=0&rename=bar.tar.gz"
+            .parse()
+            .unwrap();
+        assert_eq!(source.location, "https://example.org/foo.tar.gz");
+        assert_eq!(source.extract(), Some(false));
+        assert_eq!(source.option("rename"), Some("bar.tar.gz"));
+        assert_eq!(source.option("sha256"), None);
+
+        let source: Source = "DFFE1C2A-B461-40D5-986F-EAC7A2708D65#extract=true"
+            .parse()
+            .unwrap();
+        assert!(source.is_reference());
+        assert!(!source.is_relative());
+        assert_eq!(source.extract(), Some(true));
+    }
+
+    #[test]
+    fn reject_invalid_fragments() {
+        let invalid = |s: &str| s.parse::<Source>().is_err();
+
+        assert!(invalid("#extract=1"));
+        assert!(invalid("https://example.org/a#unknown=1"));
+        assert!(invalid("https://example.org/a#extract"));
+        assert!(invalid("https://example.org/a#extract=1&extract=0"));
+        assert!(invalid("https://example.org/a#extract=yes"));
+        assert!(invalid("https://example.org/a#rename="));
+        assert!(invalid("https://example.org/a#rename=../a"));
+        assert!(invalid("https://example.org/a#rename=.."));
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for s in [
+            "https://example.org/a",
+            "https://example.org/a.tar.gz#rename=b.tar.gz&extract=1",
+            "git+https://example.org/foo.git#branch=main&rename=bar",
+        ] {
+            let source: Source = s.parse().unwrap();
+            assert_eq!(source.to_string(), s);
+        }
+
+        let mut source: Source = "https://example.org/a#extract=1&rename=b".parse().unwrap();
+        assert_eq!(source.take_option("extract").as_deref(), Some("1"));
+        assert_eq!(source.take_option("extract"), None);
+        assert_eq!(source.to_string(), "https://example.org/a#rename=b");
+    }
+
+    #[test]
+    fn file_names() {
+        let source = Source::new("https://example.org/dl/foo.tar.gz");
+        assert_eq!(source.file_name(&source.location), Some("foo.tar.gz"));
+        assert_eq!(source.file_name("https://example.org/dl/"), None);
+
+        let source: Source = "https://example.org/dl/#rename=foo.conf".parse().unwrap();
+        assert_eq!(
+            source.file_name("https://example.org/dl/"),
+            Some("foo.conf")
+        );
+    }
+
+    #[test]
+    fn git_revisions() {
+        let source: Source = "git+https://example.org/foo.git#tag=v1.2".parse().unwrap();
+        assert!(source.is_git());
+        assert_eq!(source.git_url(), Some("https://example.org/foo.git"));
+        assert_eq!(source.git_revision(), GitRevision::Tag("v1.2".to_owned()));
+
+        let source: Source = format!("git+file:///srv/foo#commit={COMMIT}")
+            .parse()
+            .unwrap();
+        assert_eq!(
+            source.git_revision(),
+            GitRevision::Commit(COMMIT.to_owned())
+        );
+
+        let source: Source = "git+https://example.org/foo#branch=main".parse().unwrap();
+        assert_eq!(
+            source.git_revision(),
+            GitRevision::Branch("main".to_owned())
+        );
+
+        let source: Source = "git+https://example.org/foo".parse().unwrap();
+        assert_eq!(source.git_revision(), GitRevision::Default);
+    }
+
+    #[test]
+    fn git_commit_must_be_full_hash() {
+        assert!("git+https://example.org/foo#commit=0f080a7"
+            .parse::<Source>()
+            .is_err());
+        assert!(format!("git+https://example.org/foo#commit={COMMIT}0")
+            .parse::<Source>()
+            .is_err());
+        assert!(format!(
+            "git+https://example.org/foo#commit={}",
+            &COMMIT.replace('0', "g")
+        )
+        .parse::<Source>()
+        .is_err());
+    }
+
+    #[test]
+    fn relative_sources() {
+        let dir = std::env::temp_dir().join(format!("pkgbuild-relative-{}", std::process::id()));
+        let bundle = dir.join("bundle");
+        fs::create_dir_all(bundle.join("patches")).unwrap();
+        fs::write(bundle.join("patches/a.patch"), b"").unwrap();
+        fs::write(dir.join("secret"), b"").unwrap();
+
+        let resolve =
+            |location: &str, origin: Option<&Path>| Source::new(location).resolve_relative(origin);
+
+        let resolved = resolve("patches/a.patch", Some(&bundle)).unwrap();
+        assert_eq!(
+            Path::new(&resolved),
+            fs::canonicalize(bundle.join("patches/a.patch")).unwrap()
+        );
+        assert_eq!(
+            resolve("https://example.org/a", Some(&bundle)).unwrap(),
+            "https://example.org/a"
+        );
+
+        assert!(resolve("/etc/passwd", Some(&bundle)).is_err());
+        assert!(resolve("../../etc/shadow", Some(&bundle)).is_err());
+        assert!(resolve("patches/../../secret", Some(&bundle)).is_err());
+        assert!(resolve("patches/missing.patch", Some(&bundle)).is_err());
+        assert!(resolve("patches/a.patch", None).is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("secret"), bundle.join("patches/link")).unwrap();
+            assert!(resolve("patches/link", Some(&bundle)).is_err());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn git_options() {
+        // Revisions only apply to git sources and exclude each other
+        assert!("https://example.org/foo.tar.gz#tag=v1"
+            .parse::<Source>()
+            .is_err());
+        assert!("git+https://example.org/foo#tag=v1&branch=main"
+            .parse::<Source>()
+            .is_err());
+        assert!("git+https://example.org/foo#branch=-f"
+            .parse::<Source>()
+            .is_err());
+        assert!("git+https://example.org/foo#tag="
+            .parse::<Source>()
+            .is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{Dependency, PackageBuild};
+use serde::{Deserialize, Serialize};
+
+/// An additional package produced by a packagebuild, such as `foo-dev` or `foo-doc`.
+///
+/// Its files are either installed by its own `package` phase (`package_<name>`) or moved
+/// out of the install directory of the main package by the globs in `files` (`files_<name>`).
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SplitPackage {
+    /// The name of the split package
+    pub name: String,
+    /// The description of the split package
+    pub description: Option<String>,
+    /// The packages the split package provides
+    pub provides: Option<Vec<String>>,
+    /// The runtime dependencies of the split package
+    pub extra_dependencies: Option<Vec<Dependency>>,
+    /// The optional dependencies of the split package
+    pub optional_dependencies: Option<Vec<Dependency>>,
+    /// Globs of the paths to move out of the install directory of the main package,
+    /// relative to it
+    pub files: Option<Vec<String>>,
+    /// The `package` phase of the split package, run after the one of the main package
+    pub package: Option<Vec<String>>,
+}
+
+impl SplitPackage {
+    /// Creates a new split package without any files
+    /// # Arguments
+    /// * `name` - The name of the split package
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            description: None,
+            provides: None,
+            extra_dependencies: None,
+            optional_dependencies: None,
+            files: None,
+            package: None,
+        }
+    }
+}
+
+impl PackageBuild {
+    /// Returns the split package with the supplied name, if there is one
+    /// # Arguments
+    /// * `name` - The name of the split package
+    pub fn split_package(&self, name: &str) -> Option<&SplitPackage> {
+        self.split_packages
+            .iter()
+            .flatten()
+            .find(|split| split.name == name)
+    }
+
+    /// Returns the names of all the packages this packagebuild produces,
+    /// the main package comes first
+    pub fn output_names(&self) -> Vec<&str> {
+        let mut names = vec![self.name.as_str()];
+        names.extend(
+            self.split_packages
+                .iter()
+                .flatten()
+                .map(|s| s.name.as_str()),
+        );
+        names
+    }
+}
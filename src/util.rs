@@ -25,3 +25,97 @@ pub fn clean_dir(path: &Path) -> Result<(), std::io::Error> {
 
     ensure_dir(path)
 }
+
+/// Checks if the supplied string looks like an email address (`local@domain.tld`)
+/// # Arguments
+/// * `address` - The string to check
+pub fn is_email_address(address: &str) -> bool {
+    let (local, domain) = match address.split_once('@') {
+        Some(v) => v,
+        None => return false,
+    };
+
+    !local.is_empty()
+        && !domain.contains('@')
+        && !address.chars().any(|c| c.is_whitespace() || c.is_control())
+        && domain.split('.').count() >= 2
+        && domain.split('.').all(|part| !part.is_empty())
+}
+
+/// Quotes the supplied string for use as a single word in a POSIX shell
+/// # Arguments
+/// * `value` - The string to quote
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Checks if the supplied path matches a glob pattern.
+///
+/// `*` matches any characters except `/`, `?` matches a single character except `/`
+/// and `**` matches any characters, including `/`. A `**/` may also match no directory at all.
+/// # Arguments
+/// * `pattern` - The pattern to match against
+/// * `path` - The `/`-separated path to match
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    fn matches(p: &[char], s: &[char]) -> bool {
+        match p.first() {
+            None => s.is_empty(),
+            Some('*') if p.get(1) == Some(&'*') => {
+                let rest = &p[2..];
+                if let Some(after) = rest.strip_prefix(&['/']) {
+                    if matches(after, s) {
+                        return true;
+                    }
+                }
+                (0..=s.len()).any(|i| matches(rest, &s[i..]))
+            }
+            Some('*') => (0..=s.len())
+                .take_while(|i| *i == 0 || s[i - 1] != '/')
+                .any(|i| matches(&p[1..], &s[i..])),
+            Some('?') => s.first().is_some_and(|c| *c != '/') && matches(&p[1..], &s[1..]),
+            Some(c) => s.first() == Some(c) && matches(&p[1..], &s[1..]),
+        }
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    matches(&pattern, &path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_single_star() {
+        assert!(glob_match("*.h", "foo.h"));
+        assert!(!glob_match("*.h", "include/foo.h"));
+        assert!(glob_match("usr/lib/*.so*", "usr/lib/libfoo.so.1"));
+        assert!(!glob_match("usr/lib/*.so", "usr/lib/libfoo.a"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn glob_question_mark() {
+        assert!(glob_match("lib?.a", "libx.a"));
+        assert!(!glob_match("lib?.a", "lib.a"));
+        assert!(!glob_match("a?b", "a/b"));
+    }
+
+    #[test]
+    fn glob_double_star() {
+        assert!(glob_match("usr/include/**", "usr/include/foo/bar.h"));
+        assert!(glob_match("usr/**/*.h", "usr/include/foo/bar.h"));
+        assert!(glob_match("usr/**/*.h", "usr/bar.h"));
+        assert!(glob_match("**/*.pc", "lib/pkgconfig/foo.pc"));
+        assert!(glob_match("**/*.pc", "foo.pc"));
+        assert!(!glob_match("usr/**/*.h", "lib/bar.h"));
+    }
+
+    #[test]
+    fn glob_literal() {
+        assert!(glob_match("usr/bin/foo", "usr/bin/foo"));
+        assert!(!glob_match("usr/bin/foo", "usr/bin/foobar"));
+        assert!(!glob_match("usr/bin", "usr/bin/foo"));
+    }
+}
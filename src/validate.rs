@@ -0,0 +1,423 @@
+use crate::util::is_email_address;
+use crate::{is_sha256, PackageBuild, Source, CHECKSUM_SKIP};
+use std::fmt::Display;
+
+/// The maximum length of a package name
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// A problem found when validating a packagebuild
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// The field the problem was found in
+    pub field: String,
+    /// A human readable description of the problem
+    pub message: String,
+}
+
+impl PackageBuild {
+    /// Validates the fields of this packagebuild semantically.
+    ///
+    /// Returns all the problems found, not only the first one
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors: Vec<ValidationError> = Vec::new();
+        let mut error = |field: &str, message: String| {
+            errors.push(ValidationError {
+                field: field.to_owned(),
+                message,
+            })
+        };
+
+        if let Err(e) = check_name(&self.name) {
+            error("name", e);
+        }
+
+        if let Err(e) = check_version(&self.version) {
+            error("version", e);
+        }
+
+        if let Some(template) = &self.inherit {
+            error(
+                "inherit",
+                format!("Template '{template}' has not been resolved, see `TemplateResolver`"),
+            );
+        }
+
+        if let Some(email) = &self.maintainer_email {
+            if !is_email_address(email) {
+                error(
+                    "maintainer_email",
+                    format!("'{email}' is not an email address"),
+                );
+            }
+        }
+
+        for name in self.provides.iter().flatten() {
+            if let Err(e) = check_name(name) {
+                error("provides", e);
+            }
+        }
+
+        let dependency_lists = [
+            ("extra_dependencies", &self.extra_dependencies),
+            ("optional_dependencies", &self.optional_dependencies),
+            ("build_dependencies", &self.build_dependencies),
+            ("cross_dependencies", &self.cross_dependencies),
+        ];
+        for (field, list) in dependency_lists {
+            for dependency in list.iter().flatten() {
+                if let Err(e) = check_name(&dependency.name) {
+                    error(field, e);
+                }
+
+                if let Some(constraint) = &dependency.constraint {
+                    let version = constraint
+                        .version
+                        .strip_suffix('*')
+                        .unwrap_or(&constraint.version);
+                    if let Err(e) = check_version(version) {
+                        error(field, format!("Constraint of '{}': {e}", dependency.name));
+                    }
+                }
+            }
+        }
+
+        for arch in self.arch.iter().flatten() {
+            if let Err(e) = check_arch(arch) {
+                error("arch", e);
+            }
+
+            if self.split_package(arch).is_some() {
+                error(
+                    "arch",
+                    format!("'{arch}' is both an architecture and a split package"),
+                );
+            }
+        }
+
+        for arch_override in self.arch_overrides.iter().flatten() {
+            if !self.arch.iter().flatten().any(|a| *a == arch_override.arch) {
+                error(
+                    "arch",
+                    format!(
+                        "'{}' is overridden, but not a supported architecture",
+                        arch_override.arch
+                    ),
+                );
+            }
+
+            if let Some(source) = &arch_override.source {
+                if !is_url(source) && !is_relative_path(source) {
+                    error(
+                        &format!("source_{}", arch_override.arch),
+                        format!("'{source}' is neither a valid URL nor a relative path"),
+                    );
+                }
+            }
+
+            if let Some(checksum) = &arch_override.source_sha256 {
+                if !is_sha256(checksum) {
+                    error(
+                        &format!("source_sha256_{}", arch_override.arch),
+                        format!("'{checksum}' is not a SHA-256 checksum"),
+                    );
+                }
+            }
+        }
+
+        if let Some(source) = &self.source {
+            if !is_url(source) && !is_relative_path(source) {
+                error(
+                    "source",
+                    format!("'{source}' is neither a valid URL nor a relative path"),
+                );
+            }
+        }
+
+        if let Some(checksum) = &self.source_sha256 {
+            if !is_sha256(checksum) {
+                error(
+                    "source_sha256",
+                    format!("'{checksum}' is not a SHA-256 checksum"),
+                );
+            }
+        }
+
+        if let Some(checksums) = &self.extra_sources_sha256 {
+            let sources = self.extra_sources.as_ref().map_or(0, |s| s.len());
+            if checksums.len() != sources {
+                error(
+                    "extra_sources_sha256",
+                    format!(
+                        "Got {} checksums for {sources} extra sources",
+                        checksums.len()
+                    ),
+                );
+            }
+
+            for checksum in checksums {
+                if checksum != CHECKSUM_SKIP && !is_sha256(checksum) {
+                    error(
+                        "extra_sources_sha256",
+                        format!("'{checksum}' is neither a SHA-256 checksum nor {CHECKSUM_SKIP}"),
+                    );
+                }
+            }
+        }
+
+        for source in self.extra_sources.iter().flatten() {
+            match source.parse::<Source>() {
+                Ok(s)
+                    if !is_url(&s.location)
+                        && !is_relative_path(&s.location)
+                        && !s.is_reference() =>
+                {
+                    error(
+                        "extra_sources",
+                        format!(
+                            "'{source}' is neither a valid URL, a relative path nor a reference"
+                        ),
+                    )
+                }
+                Ok(_) => {}
+                Err(e) => error("extra_sources", e.to_string()),
+            }
+        }
+
+        let mut outputs = vec![self.name.as_str()];
+        for split in self.split_packages.iter().flatten() {
+            if let Err(e) = check_name(&split.name) {
+                error("split_packages", e);
+            }
+
+            if outputs.contains(&split.name.as_str()) {
+                error(
+                    "split_packages",
+                    format!("The package '{}' is produced more than once", split.name),
+                );
+            }
+            outputs.push(&split.name);
+
+            for name in split.provides.iter().flatten() {
+                if let Err(e) = check_name(name) {
+                    error(&format!("provides_{}", split.name), e);
+                }
+            }
+
+            let dependency_lists = [
+                ("extra_dependencies", &split.extra_dependencies),
+                ("optional_dependencies", &split.optional_dependencies),
+            ];
+            for (field, list) in dependency_lists {
+                for dependency in list.iter().flatten() {
+                    if let Err(e) = check_name(&dependency.name) {
+                        error(&format!("{field}_{}", split.name), e);
+                    }
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Checks a package name: It must start with a lowercase letter or digit and consist of
+/// lowercase letters, digits and `+-._`, with at most `MAX_NAME_LENGTH` characters
+/// # Arguments
+/// * `name` - The name to check
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("The name is empty".to_owned());
+    }
+
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(format!(
+            "'{name}' is longer than {MAX_NAME_LENGTH} characters"
+        ));
+    }
+
+    if !name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit()) {
+        return Err(format!(
+            "'{name}' does not start with a lowercase letter or digit"
+        ));
+    }
+
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_lowercase() || c.is_ascii_digit() || "+-._".contains(*c)))
+    {
+        return Err(format!("'{name}' contains the invalid character '{c}'"));
+    }
+
+    Ok(())
+}
+
+/// Checks an architecture name: It must start with a lowercase letter and consist of
+/// lowercase letters, digits and `_`, such as `x86_64` or `aarch64`
+/// # Arguments
+/// * `arch` - The architecture to check
+pub fn check_arch(arch: &str) -> Result<(), String> {
+    if !arch.starts_with(|c: char| c.is_ascii_lowercase()) {
+        return Err(format!("'{arch}' does not start with a lowercase letter"));
+    }
+
+    if let Some(c) = arch
+        .chars()
+        .find(|c| !(c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '_'))
+    {
+        return Err(format!("'{arch}' contains the invalid character '{c}'"));
+    }
+
+    Ok(())
+}
+
+/// Checks a version: It must start with a digit or letter and consist of
+/// letters, digits and `+-._~`
+/// # Arguments
+/// * `version` - The version to check
+pub fn check_version(version: &str) -> Result<(), String> {
+    if version.is_empty() {
+        return Err("The version is empty".to_owned());
+    }
+
+    if !version.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err(format!("'{version}' does not start with a letter or digit"));
+    }
+
+    if let Some(c) = version
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || "+-._~".contains(*c)))
+    {
+        return Err(format!("'{version}' contains the invalid character '{c}'"));
+    }
+
+    Ok(())
+}
+
+/// Checks if the supplied string is a URL in the form `<scheme>://<location>`
+/// # Arguments
+/// * `url` - The string to check
+pub fn is_url(url: &str) -> bool {
+    let (scheme, location) = match url.split_once("://") {
+        Some(v) => v,
+        None => return false,
+    };
+
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        && !location.is_empty()
+        && !url.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// Checks if the supplied string is a relative path that stays below the directory
+/// it is relative to: It must not be absolute, contain `..` components or have a scheme
+/// # Arguments
+/// * `path` - The string to check
+pub fn is_relative_path(path: &str) -> bool {
+    !path.is_empty()
+        && !path.starts_with('/')
+        && !path.contains("://")
+        && path.split('/').all(|segment| segment != "..")
+        && !path.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// Checks if the supplied string is a reference (a UUID) in the form
+/// `XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX` with hexadecimal digits
+/// # Arguments
+/// * `reference` - The string to check
+pub fn is_reference(reference: &str) -> bool {
+    let groups: Vec<&str> = reference.split('-').collect();
+
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(pkgbuild: &PackageBuild) -> Vec<String> {
+        match pkgbuild.validate() {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.into_iter().map(|e| e.field).collect(),
+        }
+    }
+
+    #[test]
+    fn valid_packagebuild() {
+        let mut pkgbuild = PackageBuild::new("foo-bar+1", "1.2.3~rc1", 0);
+        pkgbuild.maintainer_email = Some("a@example.org".to_owned());
+        pkgbuild.source = Some("https://example.org/foo.tar.gz".to_owned());
+        pkgbuild.extra_sources = Some(vec![
+            "DFFE1C2A-B461-40D5-986F-EAC7A2708D65".to_owned(),
+            "https://example.org/a.patch#extract=0".to_owned(),
+        ]);
+        pkgbuild.build_dependencies = Some(vec!["cmake>=3.20".parse().unwrap()]);
+        assert_eq!(fields(&pkgbuild), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_all_problems() {
+        let mut pkgbuild = PackageBuild::new("Foo", "-1", 0);
+        pkgbuild.maintainer_email = Some("nobody".to_owned());
+        pkgbuild.source = Some("not a url".to_owned());
+        pkgbuild.provides = Some(vec!["".to_owned()]);
+        pkgbuild.inherit = Some("cmake".to_owned());
+
+        assert_eq!(
+            fields(&pkgbuild),
+            vec![
+                "name",
+                "version",
+                "inherit",
+                "maintainer_email",
+                "provides",
+                "source"
+            ]
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert!(check_name("foo").is_ok());
+        assert!(check_name("0ad").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name("-foo").is_err());
+        assert!(check_name("foo bar").is_err());
+        assert!(check_name("../x").is_err());
+        assert!(check_name(&"a".repeat(MAX_NAME_LENGTH)).is_ok());
+        assert!(check_name(&"a".repeat(MAX_NAME_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn urls_and_references() {
+        assert!(is_url("https://example.org/a"));
+        assert!(is_url("git+https://example.org/a"));
+        assert!(!is_url("https://"));
+        assert!(!is_url("example.org/a"));
+        assert!(!is_url("https://example.org/a b"));
+        assert!(is_reference("DFFE1C2A-B461-40D5-986F-EAC7A2708D65"));
+        assert!(!is_reference("DFFE1C2A-B461-40D5-986F"));
+        assert!(is_relative_path("patches/a.patch"));
+        assert!(is_relative_path("./a..b"));
+        assert!(!is_relative_path("/etc/passwd"));
+        assert!(!is_relative_path("../../etc/shadow"));
+        assert!(!is_relative_path("patches/../../a"));
+        assert!(!is_relative_path("file:///etc/passwd"));
+        assert!(!is_relative_path(""));
+    }
+}
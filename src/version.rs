@@ -0,0 +1,342 @@
+use crate::PackageBuild;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    io::{Error, ErrorKind},
+    str::FromStr,
+};
+
+/// The full version of a package in the form `[epoch:]version-real_version`.
+///
+/// Versions are ordered by epoch (missing is 0), then version, then real version.
+/// The version part is compared like `dpkg` does: Digit runs are compared numerically,
+/// letters sort before other characters and `~` sorts before everything, even the end,
+/// so pre-releases like `1.0~rc1` are older than `1.0`.
+///
+/// The common pre-release suffixes `alpha`, `beta`, `pre` and `rc` directly after a digit
+/// or after a `.`, `-` or `_` are treated as if they were prefixed with `~`, so `1.0rc1`
+/// and `1.0-beta` are older than `1.0` as well. Single letters like in `1.0.2a` are
+/// not pre-releases, they are commonly used for patch releases.
+///
+/// Equality follows the ordering, not the spelling: `1.01-0` and `1.1-0` are equal
+/// even though they are displayed differently. Compare the fields to tell them apart.
+#[derive(Debug, Clone, Eq)]
+pub struct PackageVersion {
+    /// The epoch, overriding the version if the versioning scheme of a package changes
+    pub epoch: Option<u32>,
+    /// The upstream version of the package
+    pub version: String,
+    /// The AcaciaLinux-internal version (release) of the package
+    pub real_version: u32,
+}
+
+impl PackageVersion {
+    /// Creates a new package version
+    /// # Arguments
+    /// * `epoch` - The optional epoch
+    /// * `version` - The upstream version
+    /// * `real_version` - The AcaciaLinux-internal version
+    pub fn new(epoch: Option<u32>, version: &str, real_version: u32) -> Self {
+        Self {
+            epoch,
+            version: version.to_owned(),
+            real_version,
+        }
+    }
+}
+
+impl PackageBuild {
+    /// Returns the full version of the package this packagebuild builds
+    pub fn package_version(&self) -> PackageVersion {
+        PackageVersion::new(self.epoch, &self.version, self.real_version)
+    }
+}
+
+/// The suffixes that mark pre-releases, in their order
+pub const PRERELEASE_SUFFIXES: &[&str] = &["alpha", "beta", "pre", "rc"];
+
+/// Marks the pre-release suffixes of a version with `~`: `1.0-rc1` becomes `1.0~rc1`
+/// # Arguments
+/// * `version` - The version to normalize
+fn normalize_prerelease(version: &str) -> String {
+    let mut res = String::with_capacity(version.len() + 1);
+    let mut rest = version;
+
+    while let Some(c) = rest.chars().next() {
+        let after_digit = res.ends_with(|c: char| c.is_ascii_digit());
+        let candidate = match c {
+            '.' | '-' | '_' if after_digit => &rest[1..],
+            _ => rest,
+        };
+
+        if after_digit {
+            let suffix = PRERELEASE_SUFFIXES.iter().find(|s| {
+                candidate
+                    .get(..s.len())
+                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case(s))
+                    && !candidate[s.len()..].starts_with(|c: char| c.is_ascii_alphabetic())
+            });
+
+            if let Some(suffix) = suffix {
+                res.push('~');
+                res.push_str(&candidate[..suffix.len()].to_ascii_lowercase());
+                rest = &candidate[suffix.len()..];
+                continue;
+            }
+        }
+
+        res.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    res
+}
+
+/// Compares two version strings like `dpkg` does, after marking pre-release suffixes
+/// # Arguments
+/// * `a` - The first version
+/// * `b` - The second version
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a, b) = (normalize_prerelease(a), normalize_prerelease(b));
+
+    /// The weight of a character in a non-digit run
+    fn order(c: Option<u8>) -> i32 {
+        match c {
+            None => 0,
+            Some(b'~') => -1,
+            Some(c) if c.is_ascii_digit() => 0,
+            Some(c) if c.is_ascii_alphabetic() => c as i32,
+            Some(c) => c as i32 + 256,
+        }
+    }
+
+    let is_digit = |c: Option<&u8>| c.is_some_and(|c| c.is_ascii_digit());
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() || j < b.len() {
+        // Compare the non-digit run
+        while (i < a.len() && !a[i].is_ascii_digit()) || (j < b.len() && !b[j].is_ascii_digit()) {
+            let (ac, bc) = (order(a.get(i).copied()), order(b.get(j).copied()));
+            if ac != bc {
+                return ac.cmp(&bc);
+            }
+            i += 1;
+            j += 1;
+        }
+
+        // Compare the digit run numerically
+        while a.get(i) == Some(&b'0') {
+            i += 1;
+        }
+        while b.get(j) == Some(&b'0') {
+            j += 1;
+        }
+
+        let mut first_diff = Ordering::Equal;
+        while is_digit(a.get(i)) && is_digit(b.get(j)) {
+            if first_diff == Ordering::Equal {
+                first_diff = a[i].cmp(&b[j]);
+            }
+            i += 1;
+            j += 1;
+        }
+
+        if is_digit(a.get(i)) {
+            return Ordering::Greater;
+        }
+        if is_digit(b.get(j)) {
+            return Ordering::Less;
+        }
+        if first_diff != Ordering::Equal {
+            return first_diff;
+        }
+    }
+
+    Ordering::Equal
+}
+
+impl Ord for PackageVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.epoch
+            .unwrap_or(0)
+            .cmp(&other.epoch.unwrap_or(0))
+            .then_with(|| compare_versions(&self.version, &other.version))
+            .then_with(|| self.real_version.cmp(&other.real_version))
+    }
+}
+
+impl PartialOrd for PackageVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Versions are equal if they are ordered the same, see `PackageVersion`
+impl PartialEq for PackageVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Display for PackageVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(epoch) = self.epoch {
+            write!(f, "{epoch}:")?;
+        }
+        write!(f, "{}-{}", self.version, self.real_version)
+    }
+}
+
+impl FromStr for PackageVersion {
+    type Err = Error;
+
+    /// Parses a version in the form `[epoch:]version[-real_version]`, the real version defaults to 0
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+
+        let (epoch, rest) = match s.split_once(':') {
+            Some((epoch, rest)) => (
+                Some(
+                    epoch
+                        .parse::<u32>()
+                        .map_err(|e| invalid(format!("Invalid epoch in '{s}': {e}")))?,
+                ),
+                rest,
+            ),
+            None => (None, s),
+        };
+
+        let (version, real_version) = match rest.rsplit_once('-') {
+            Some((version, real_version)) => (
+                version,
+                real_version
+                    .parse::<u32>()
+                    .map_err(|e| invalid(format!("Invalid real version in '{s}': {e}")))?,
+            ),
+            None => (rest, 0),
+        };
+
+        if version.is_empty() {
+            return Err(invalid(format!("Missing version in '{s}'")));
+        }
+
+        Ok(Self::new(epoch, version, real_version))
+    }
+}
+
+impl Serialize for PackageVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for PackageVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_order(versions: &[&str]) {
+        for pair in versions.windows(2) {
+            assert_eq!(
+                compare_versions(pair[0], pair[1]),
+                Ordering::Less,
+                "{} < {}",
+                pair[0],
+                pair[1]
+            );
+            assert_eq!(compare_versions(pair[1], pair[0]), Ordering::Greater);
+        }
+    }
+
+    #[test]
+    fn numeric_ordering() {
+        assert_order(&["0.9", "1", "1.0.1", "1.2", "1.10", "2.0", "10"]);
+        assert_eq!(compare_versions("1.01", "1.1"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0", "1.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn letters_and_separators() {
+        assert_order(&["1.0", "1.0.2", "1.0.2a", "1.0.2b", "1.0.3"]);
+        assert_order(&["1.0a", "1.0+b", "1.0.1"]);
+    }
+
+    #[test]
+    fn prereleases() {
+        assert_order(&[
+            "1.0~~",
+            "1.0~alpha",
+            "1.0alpha2",
+            "1.0-beta",
+            "1.0.pre1",
+            "1.0rc1",
+            "1.0_rc2",
+            "1.0",
+            "1.0.1~rc1",
+            "1.0.1",
+        ]);
+        assert_eq!(compare_versions("1.0rc1", "1.0~rc1"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0RC1", "1.0rc1"), Ordering::Equal);
+        // Not pre-release suffixes
+        assert_eq!(compare_versions("1.0-src", "1.0"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0rcx", "1.0"), Ordering::Greater);
+        // Multi-byte characters where a suffix could start
+        assert_eq!(compare_versions("1ab€", "1"), Ordering::Greater);
+        assert_eq!(compare_versions("1.€", "1.€"), Ordering::Equal);
+    }
+
+    #[test]
+    fn package_version_ordering() {
+        let v = |s: &str| s.parse::<PackageVersion>().unwrap();
+        assert!(v("1.0-1") < v("1.0-2"));
+        assert!(v("1.0-9") < v("1.1-0"));
+        assert!(v("2.0-0") < v("1:1.0-0"));
+        assert_eq!(v("0:1.0-0"), v("1.0"));
+        // Equal by ordering, yet spelled differently
+        assert_eq!(v("1.01-0"), v("1.1-0"));
+        assert_ne!(v("1.01-0").to_string(), v("1.1-0").to_string());
+        assert!(v("1.0rc1-5") < v("1.0-0"));
+    }
+
+    #[test]
+    fn parse_and_display() {
+        let version: PackageVersion = "2:1.2-rc1-3".parse().unwrap();
+        assert_eq!(version.epoch, Some(2));
+        assert_eq!(version.version, "1.2-rc1");
+        assert_eq!(version.real_version, 3);
+        assert_eq!(version.to_string(), "2:1.2-rc1-3");
+
+        assert_eq!(
+            "1.0".parse::<PackageVersion>().unwrap().to_string(),
+            "1.0-0"
+        );
+        assert!("x:1.0".parse::<PackageVersion>().is_err());
+        assert!("1.0-x".parse::<PackageVersion>().is_err());
+        assert!(":1.0".parse::<PackageVersion>().is_err());
+        assert!("-1".parse::<PackageVersion>().is_err());
+    }
+
+    #[test]
+    fn packagebuild_epoch() {
+        let mut pkgbuild = PackageBuild::new("foo", "1.0", 1);
+        assert_eq!(pkgbuild.package_version().to_string(), "1.0-1");
+        pkgbuild.epoch = Some(1);
+        assert_eq!(pkgbuild.package_version().to_string(), "1:1.0-1");
+        assert!(pkgbuild.package_version() > "2.0-0".parse().unwrap());
+    }
+
+    #[test]
+    fn serde() {
+        let version: PackageVersion = serde_json::from_str("\"1:2.0-3\"").unwrap();
+        assert_eq!(version, PackageVersion::new(Some(1), "2.0", 3));
+        assert_eq!(serde_json::to_string(&version).unwrap(), "\"1:2.0-3\"");
+    }
+}